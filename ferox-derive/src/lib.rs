@@ -0,0 +1,257 @@
+//! Procedural macros for `ferox`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, punctuated::Punctuated, Attribute, Data, DeriveInput, Expr, ExprLit, Fields,
+    GenericArgument, Lit, LitStr, Meta, PathArguments, Token, Type,
+};
+
+/// Turns an enum into an ASCII command set.
+///
+/// Every variant is one command. Its name on the wire is given by `#[command(name = "...")]`,
+/// an existing `#[serde(rename = "...")]`, or else the lowercased variant name. Other accepted
+/// names are listed with `#[command(alias = "...")]`, repeated as needed. The macro adds the
+/// matching `#[serde(rename)]` and `#[serde(alias)]` and implements
+/// `ferox::proto::command::Commands` with a table of command names, aliases, argument types,
+/// get/set capability and doc comments.
+///
+/// Get and set are inferred from the arguments: a command without arguments can only be read,
+/// one with a single optional argument can be read (`name?`) and written (`name value`), and
+/// anything else can only be written. `#[command(get)]` and/or `#[command(set)]` override this.
+///
+/// Must come before `#[derive(Serialize, Deserialize)]` so that serde sees the renames.
+#[proc_macro_attribute]
+pub fn ascii_command(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            TokenStream2::from(attr).into_iter().next().unwrap().span(),
+            "ascii_command takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let mut input = parse_macro_input!(item as DeriveInput);
+    match expand(&mut input) {
+        Ok(table) => quote!(#input #table).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    name: Option<LitStr>,
+    aliases: Vec<LitStr>,
+    get: bool,
+    set: bool,
+}
+
+fn expand(input: &mut DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &mut input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "ascii_command only supports enums",
+        ));
+    };
+
+    let mut commands = Vec::new();
+    for variant in &mut data.variants {
+        let options = take_options(&mut variant.attrs)?;
+        let renamed = serde_rename(&variant.attrs)?;
+        let name = match (&options.name, &renamed) {
+            (Some(name), Some(renamed)) if name.value() != renamed.value() => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "command name differs from #[serde(rename)]",
+                ));
+            }
+            (_, Some(renamed)) => renamed.value(),
+            (Some(name), None) => name.value(),
+            (None, None) => variant.ident.to_string().to_lowercase(),
+        };
+        if renamed.is_none() {
+            variant
+                .attrs
+                .push(syn::parse_quote!(#[serde(rename = #name)]));
+        }
+        let aliases = &options.aliases;
+        for alias in aliases {
+            variant
+                .attrs
+                .push(syn::parse_quote!(#[serde(alias = #alias)]));
+        }
+
+        let args: Vec<(Option<String>, &Type)> = match &variant.fields {
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) => fields.unnamed.iter().map(|f| (None, &f.ty)).collect(),
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .map(|f| (f.ident.as_ref().map(ToString::to_string), &f.ty))
+                .collect(),
+        };
+        let (get, set) = if options.get || options.set {
+            (options.get, options.set)
+        } else {
+            match args.as_slice() {
+                [] => (true, false),
+                [(_, ty)] if is_option(ty) => (true, true),
+                _ => (false, true),
+            }
+        };
+
+        let args = args.iter().map(|(name, ty)| {
+            let name = match name {
+                Some(name) => quote!(Some(#name)),
+                None => quote!(None),
+            };
+            let ty_name = type_name(ty);
+            let optional = is_option(ty);
+            quote! {
+                ::ferox::proto::command::ArgInfo {
+                    name: #name,
+                    ty: #ty_name,
+                    optional: #optional,
+                }
+            }
+        });
+        let doc = doc_string(&variant.attrs);
+        commands.push(quote! {
+            ::ferox::proto::command::CommandInfo {
+                name: #name,
+                aliases: &[#(#aliases),*],
+                args: &[#(#args),*],
+                get: #get,
+                set: #set,
+                doc: #doc,
+            }
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::ferox::proto::command::Commands for #ident #ty_generics #where_clause {
+            const COMMANDS: &'static [::ferox::proto::command::CommandInfo] = &[#(#commands),*];
+        }
+    })
+}
+
+// Removes the `#[command(...)]` attributes of a variant and returns their contents.
+fn take_options(attrs: &mut Vec<Attribute>) -> syn::Result<Options> {
+    let mut options = Options::default();
+    let mut result = Ok(());
+    attrs.retain(|attr| {
+        if !attr.path().is_ident("command") {
+            return true;
+        }
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("alias") {
+                options.aliases.push(meta.value()?.parse()?);
+            } else if meta.path.is_ident("get") {
+                options.get = true;
+            } else if meta.path.is_ident("set") {
+                options.set = true;
+            } else {
+                return Err(meta.error("expected `name`, `alias`, `get` or `set`"));
+            }
+            Ok(())
+        });
+        if result.is_ok() {
+            result = parsed;
+        }
+        false
+    });
+    result.map(|_| options)
+}
+
+fn serde_rename(attrs: &[Attribute]) -> syn::Result<Option<LitStr>> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            if let Meta::NameValue(nv) = meta {
+                if let (
+                    true,
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(s), ..
+                    }),
+                ) = (nv.path.is_ident("rename"), nv.value)
+                {
+                    return Ok(Some(s));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn doc_string(attrs: &[Attribute]) -> String {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join(" ")
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p.path.segments.last().is_some_and(|s| s.ident == "Option"),
+        _ => false,
+    }
+}
+
+// Spells a type the way it is written in source, without lifetimes, e.g. `Option<&[u8]>`.
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Path(p) => p
+            .path
+            .segments
+            .iter()
+            .map(|segment| {
+                let mut s = segment.ident.to_string();
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    let args: Vec<String> = args
+                        .args
+                        .iter()
+                        .filter_map(|arg| match arg {
+                            GenericArgument::Lifetime(_) => None,
+                            GenericArgument::Type(ty) => Some(type_name(ty)),
+                            other => Some(other.to_token_stream().to_string()),
+                        })
+                        .collect();
+                    if !args.is_empty() {
+                        s = format!("{}<{}>", s, args.join(", "));
+                    }
+                }
+                s
+            })
+            .collect::<Vec<_>>()
+            .join("::"),
+        Type::Reference(r) => {
+            let mutability = if r.mutability.is_some() { "mut " } else { "" };
+            format!("&{}{}", mutability, type_name(&r.elem))
+        }
+        Type::Slice(s) => format!("[{}]", type_name(&s.elem)),
+        Type::Array(a) => format!("[{}; {}]", type_name(&a.elem), a.len.to_token_stream()),
+        Type::Tuple(t) => {
+            let elems: Vec<String> = t.elems.iter().map(type_name).collect();
+            format!("({})", elems.join(", "))
+        }
+        Type::Paren(p) => type_name(&p.elem),
+        Type::Group(g) => type_name(&g.elem),
+        other => other.to_token_stream().to_string(),
+    }
+}
@@ -4,25 +4,68 @@ use core::{
 };
 
 use defmt_or_log::{debug, info};
+use embedded_io::Error as _;
 use embedded_io_async::{Read, Write};
-use heapless::String;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    proto::{error::Error, Result},
+    proto::{
+        ascii::{
+            float::FloatFormat,
+            hex::Hex,
+            number,
+            quote::{needs_quoting, write_quoted},
+            stream::StreamDeserializer,
+            to_slice_with_format,
+        },
+        command::ascii_command,
+        error::{Error, ErrorReport},
+        Result,
+    },
+    uart::{
+        drain,
+        echo::{strip_echo, EchoMode},
+        framed::FramedReader,
+        protocol::{self, Protocol},
+    },
     MAX_STRING_SIZE,
 };
 
-const CRLF: &[u8] = b"\r\n";
-const CRLF_PROMPT: &[u8] = b"\r\n>>";
+/// Commands that change the board every time they run, so a failed one must not be sent again.
+pub const NON_IDEMPOTENT: &[&str] = &["save", "errclr"];
 
+const DEVICE: &str = "ctl200";
+// Longest single line of the `status` dump.
+const STATUS_LINE_SIZE: usize = 64;
+
+#[ascii_command]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Ctl200Request {
-    #[serde(rename = "version")]
+    /// Firmware version.
     Version,
 }
 
+/// Summary of the board status, as printed by the `status` command.
+///
+/// Values the firmware does not report are `None`.
+#[derive(Deserialize, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BoardStatus {
+    pub lason: Option<bool>,
+    pub ilaser: Option<f32>,
+    pub vlaser: Option<f32>,
+    pub tecon: Option<bool>,
+    pub rtset: Option<f32>,
+    pub rtact: Option<f32>,
+    pub itec: Option<f32>,
+    pub vtec: Option<f32>,
+    pub iphd: Option<f32>,
+    pub tboard: Option<f32>,
+    /// Error flags, printed in hex.
+    pub err: Option<Hex<i32>>,
+}
+
 // Trait definition with lifetime parameter
 trait FromBytes<'a> {
     fn from_bytes(bytes: &'a [u8]) -> Result<Self>
@@ -51,20 +94,16 @@ impl<'a> FromBytes<'a> for bool {
 // Implementation for i32
 impl<'a> FromBytes<'a> for i32 {
     fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
-        core::str::from_utf8(bytes)
-            .map_err(|_| Error::BytesToUTF8Error)?
-            .parse()
-            .map_err(|_| Error::ParseIntError)
+        let v =
+            number::parse_int(core::str::from_utf8(bytes).map_err(|_| Error::BytesToUTF8Error)?)?;
+        i32::try_from(v).map_err(|_| Error::IntegerOverflow)
     }
 }
 
 // Implementation for f32
 impl<'a> FromBytes<'a> for f32 {
     fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
-        core::str::from_utf8(bytes)
-            .map_err(|_| Error::BytesToUTF8Error)?
-            .parse()
-            .map_err(|_| Error::ParseFloatError)
+        number::parse_float(core::str::from_utf8(bytes).map_err(|_| Error::BytesToUTF8Error)?)
     }
 }
 
@@ -76,8 +115,12 @@ where
     U: Read + Write + 'static,
 {
     uart: U,
+    reader: FramedReader<MAX_STRING_SIZE>,
     buf: [u8; MAX_STRING_SIZE],
-    buf_pos: usize,
+    float_format: FloatFormat,
+    line_ending: &'static [u8],
+    prompt: &'static [u8],
+    echo: EchoMode,
 }
 
 impl<U> Ctl200<U>
@@ -85,29 +128,47 @@ where
     U: Read + Write + 'static,
 {
     pub fn new(uart: U) -> Self {
+        Self::with_protocol(uart, protocol::ctl200())
+    }
+
+    /// Talks to the board with the line ending, prompt and echo handling of `protocol`, e.g.
+    /// [`protocol::ctl200`] with a bare `\r` line ending for boards behind a converter that
+    /// turns it into `\r\n`. The post-processor is not used.
+    pub fn with_protocol<P>(uart: U, protocol: Protocol<P>) -> Self {
         Ctl200 {
             uart,
+            reader: FramedReader::new(),
             buf: [0; MAX_STRING_SIZE],
-            buf_pos: 0,
+            float_format: FloatFormat::default(),
+            line_ending: protocol.line_ending,
+            prompt: protocol.terminator,
+            echo: protocol.echo,
         }
     }
 
+    /// Sets how float values such as setpoints are written, e.g. [`FloatFormat::fixed`] for
+    /// firmware that expects a fixed number of decimals.
+    pub fn with_float_format(mut self, format: FloatFormat) -> Self {
+        self.float_format = format;
+        self
+    }
+
     /// Returns the enabled state of the laser.
-    pub async fn laser_en(&mut self) -> Result<bool> {
+    pub async fn laser_en(&mut self) -> Result<bool, ErrorReport> {
         let is_on = self.get::<i32>("lason").await? == 1;
         debug!("lason: {}", is_on);
         Ok(is_on)
     }
 
     /// Sets the enabled state of the laser.
-    pub async fn set_laser_en(&mut self, en: bool) -> Result<()> {
+    pub async fn set_laser_en(&mut self, en: bool) -> Result<(), ErrorReport> {
         debug!("set lason: {}", en);
         self.set("lason", Value::Bool(en)).await
     }
 
     /// Returns the laser current in mA.
     #[allow(non_snake_case)]
-    pub async fn laser_current_mA(&mut self) -> Result<f32> {
+    pub async fn laser_current_mA(&mut self) -> Result<f32, ErrorReport> {
         let i_mA = self.get::<f32>("ilaser").await?;
         debug!("ilaser: {} mA", i_mA);
         Ok(i_mA)
@@ -115,35 +176,35 @@ where
 
     /// Sets the laser current in mA.
     #[allow(non_snake_case)]
-    pub async fn set_laser_current_mA(&mut self, i_mA: f32) -> Result<()> {
+    pub async fn set_laser_current_mA(&mut self, i_mA: f32) -> Result<(), ErrorReport> {
         debug!("set ilaser: {} mA", i_mA);
         self.set("ilaser", Value::Float(i_mA)).await
     }
 
     /// Returns the laser voltage in V.
     #[allow(non_snake_case)]
-    pub async fn laser_V(&mut self) -> Result<f32> {
+    pub async fn laser_V(&mut self) -> Result<f32, ErrorReport> {
         let volts = self.get::<f32>("vlaser").await?;
         debug!("vlaser: {} V", volts);
         Ok(volts)
     }
 
     /// Returns the laser turn-on delay in ms.
-    pub async fn laser_delay_ms(&mut self) -> Result<f32> {
+    pub async fn laser_delay_ms(&mut self) -> Result<f32, ErrorReport> {
         let delay_ms = self.get::<f32>("ldelay").await?;
         debug!("ldelay: {} ms", delay_ms);
         Ok(delay_ms)
     }
 
     /// Sets the laser turn-on delay in ms.
-    pub async fn set_laser_delay_ms(&mut self, delay_ms: f32) -> Result<()> {
+    pub async fn set_laser_delay_ms(&mut self, delay_ms: f32) -> Result<(), ErrorReport> {
         debug!("set ldelay: {} ms", delay_ms);
         self.set("ldelay", Value::Float(delay_ms)).await
     }
 
     /// Returns the laser current limit in mA.
     #[allow(non_snake_case)]
-    pub async fn current_limit_mA(&mut self) -> Result<f32> {
+    pub async fn current_limit_mA(&mut self) -> Result<f32, ErrorReport> {
         let limit_mA = self.get::<f32>("ilmax").await?;
         debug!("ilmax: {} mA", limit_mA);
         Ok(limit_mA)
@@ -151,27 +212,27 @@ where
 
     /// Sets the laser current limit in mA.
     #[allow(non_snake_case)]
-    pub async fn set_current_limit_mA(&mut self, limit_mA: f32) -> Result<()> {
+    pub async fn set_current_limit_mA(&mut self, limit_mA: f32) -> Result<(), ErrorReport> {
         debug!("set ilmax: {} mA", limit_mA);
         self.set("ilmax", Value::Float(limit_mA)).await
     }
 
     /// Returns the enabled state of the laser interlock.
-    pub async fn interlock_en(&mut self) -> Result<bool> {
+    pub async fn interlock_en(&mut self) -> Result<bool, ErrorReport> {
         let is_on = self.get::<i32>("lckon").await? == 1;
         debug!("lckon: {}", is_on);
         Ok(is_on)
     }
 
     /// Sets the enabled state of the laser interlock.
-    pub async fn set_interlock_en(&mut self, en: bool) -> Result<()> {
+    pub async fn set_interlock_en(&mut self, en: bool) -> Result<(), ErrorReport> {
         debug!("set lckon: {}", en);
         self.set("lckon", Value::Bool(en)).await
     }
 
     /// Returns the laser current modulation gain in mA/V.
     #[allow(non_snake_case)]
-    pub async fn laser_current_mod_gain_mA_V(&mut self) -> Result<f32> {
+    pub async fn laser_current_mod_gain_mA_V(&mut self) -> Result<f32, ErrorReport> {
         let gain = self.get::<f32>("lmodgain").await?;
         debug!("lmodgain: {} mA/V", gain);
         Ok(gain)
@@ -179,40 +240,43 @@ where
 
     /// Sets the laser current modulation gain in mA/V.
     #[allow(non_snake_case)]
-    pub async fn set_laser_current_mod_gain_mA_V(&mut self, gain_mA_V: f32) -> Result<()> {
+    pub async fn set_laser_current_mod_gain_mA_V(
+        &mut self,
+        gain_mA_V: f32,
+    ) -> Result<(), ErrorReport> {
         debug!("set lmodgain: {} mA/V", gain_mA_V);
         self.set("lmodgain", Value::Float(gain_mA_V)).await
     }
 
     /// Returns the enabled state of the TEC.
-    pub async fn tec_en(&mut self) -> Result<bool> {
+    pub async fn tec_en(&mut self) -> Result<bool, ErrorReport> {
         let is_on = self.get::<i32>("tecon").await? == 1;
         debug!("tecon: {}", is_on);
         Ok(is_on)
     }
 
     /// Sets the enabled state of the TEC.
-    pub async fn set_tec_en(&mut self, en: bool) -> Result<()> {
+    pub async fn set_tec_en(&mut self, en: bool) -> Result<(), ErrorReport> {
         debug!("set tecon: {}", en);
         self.set("tecon", Value::Bool(en)).await
     }
 
     /// Returns the enabled state of the temperature protection.
-    pub async fn temp_prot_en(&mut self) -> Result<bool> {
+    pub async fn temp_prot_en(&mut self) -> Result<bool, ErrorReport> {
         let is_on = self.get::<i32>("tprot").await? == 1;
         debug!("tprot: {}", is_on);
         Ok(is_on)
     }
 
     /// Sets the enabled state of the temperature protection.
-    pub async fn set_temp_prot_en(&mut self, en: bool) -> Result<()> {
+    pub async fn set_temp_prot_en(&mut self, en: bool) -> Result<(), ErrorReport> {
         debug!("set tprot: {}", en);
         self.set("tprot", Value::Bool(en)).await
     }
 
     /// Returns the thermistor setpoint in Ohms.
     #[allow(non_snake_case)]
-    pub async fn temp_set_Ohm(&mut self) -> Result<f32> {
+    pub async fn temp_set_Ohm(&mut self) -> Result<f32, ErrorReport> {
         let setpoint_ohms = self.get::<f32>("rtset").await?;
         debug!("rtset: {} Ohms", setpoint_ohms);
         Ok(setpoint_ohms)
@@ -220,14 +284,14 @@ where
 
     /// Sets the thermistor setpoint in Ohms.
     #[allow(non_snake_case)]
-    pub async fn set_temp_set_Ohm(&mut self, setpoint_Ohms: f32) -> Result<()> {
+    pub async fn set_temp_set_Ohm(&mut self, setpoint_Ohms: f32) -> Result<(), ErrorReport> {
         debug!("set rtset: {} Ohms", setpoint_Ohms);
         self.set("rtset", Value::Float(setpoint_Ohms)).await
     }
 
     /// Returns the actual thermistor reading in Ohms.
     #[allow(non_snake_case)]
-    pub async fn temp_act_Ohm(&mut self) -> Result<f32> {
+    pub async fn temp_act_Ohm(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("rtact").await?;
         debug!("rtact: {} Ohms", curr_val);
         Ok(curr_val)
@@ -235,7 +299,7 @@ where
 
     /// Returns the TEC current in A.
     #[allow(non_snake_case)]
-    pub async fn tec_current_A(&mut self) -> Result<f32> {
+    pub async fn tec_current_A(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("itec").await?;
         debug!("itec: {} A", curr_val);
         Ok(curr_val)
@@ -243,54 +307,54 @@ where
 
     /// Returns the TEC voltage in V.
     #[allow(non_snake_case)]
-    pub async fn tec_voltage_V(&mut self) -> Result<f32> {
+    pub async fn tec_voltage_V(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("vtec").await?;
         debug!("vtec: {} V", curr_val);
         Ok(curr_val)
     }
 
     /// Returns the proportional gain of the temperature controller.
-    pub async fn prop_gain(&mut self) -> Result<f32> {
+    pub async fn prop_gain(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("pgain").await?;
         debug!("pgain: {}", curr_val);
         Ok(curr_val)
     }
 
     /// Sets the proportional gain of the temperature controller.
-    pub async fn set_prop_gain(&mut self, gain: f32) -> Result<()> {
+    pub async fn set_prop_gain(&mut self, gain: f32) -> Result<(), ErrorReport> {
         debug!("set pgain: {}", gain);
         self.set("pgain", Value::Float(gain)).await
     }
 
     /// Returns the integral gain of the temperature controller.
-    pub async fn int_gain(&mut self) -> Result<f32> {
+    pub async fn int_gain(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("igain").await?;
         debug!("igain: {}", curr_val);
         Ok(curr_val)
     }
 
     /// Sets the integral gain of the temperature controller.
-    pub async fn set_int_gain(&mut self, gain: f32) -> Result<()> {
+    pub async fn set_int_gain(&mut self, gain: f32) -> Result<(), ErrorReport> {
         debug!("set igain: {}", gain);
         self.set("igain", Value::Float(gain)).await
     }
 
     /// Returns the differential gain of the temperature controller.
-    pub async fn diff_gain(&mut self) -> Result<f32> {
+    pub async fn diff_gain(&mut self) -> Result<f32, ErrorReport> {
         let curr_val = self.get::<f32>("dgain").await?;
         debug!("dgain: {}", curr_val);
         Ok(curr_val)
     }
 
     /// Sets the differential gain of the temperature controller.
-    pub async fn set_diff_gain(&mut self, gain: f32) -> Result<()> {
+    pub async fn set_diff_gain(&mut self, gain: f32) -> Result<(), ErrorReport> {
         debug!("set dgain: {}", gain);
         self.set("dgain", Value::Float(gain)).await
     }
 
     /// Returns the lower temperature limit in Ohms.
     #[allow(non_snake_case)]
-    pub async fn temp_min_Ohm(&mut self) -> Result<f32> {
+    pub async fn temp_min_Ohm(&mut self) -> Result<f32, ErrorReport> {
         let value = self.get::<f32>("rtmin").await?;
         debug!("rtmin: {} Ohms", value);
         Ok(value)
@@ -298,14 +362,14 @@ where
 
     /// Sets the lower temperature limit in Ohms.
     #[allow(non_snake_case)]
-    pub async fn set_temp_min_Ohm(&mut self, min: f32) -> Result<()> {
+    pub async fn set_temp_min_Ohm(&mut self, min: f32) -> Result<(), ErrorReport> {
         debug!("set rtmin: {} Ohms", min);
         self.set("rtmin", Value::Float(min)).await
     }
 
     /// Returns the upper temperature limit in Ohms.
     #[allow(non_snake_case)]
-    pub async fn temp_max_Ohm(&mut self) -> Result<f32> {
+    pub async fn temp_max_Ohm(&mut self) -> Result<f32, ErrorReport> {
         let value = self.get::<f32>("rtmax").await?;
         debug!("rtmax: {} Ohms", value);
         Ok(value)
@@ -313,14 +377,14 @@ where
 
     /// Sets the upper temperature limit in Ohms.
     #[allow(non_snake_case)]
-    pub async fn set_temp_max_Ohm(&mut self, max: f32) -> Result<()> {
+    pub async fn set_temp_max_Ohm(&mut self, max: f32) -> Result<(), ErrorReport> {
         debug!("set rtmax: {} Ohms", max);
         self.set("rtmax", Value::Float(max)).await
     }
 
     /// Returns the minimum TEC voltage in V.
     #[allow(non_snake_case)]
-    pub async fn tec_min_V(&mut self) -> Result<f32> {
+    pub async fn tec_min_V(&mut self) -> Result<f32, ErrorReport> {
         let val = self.get::<f32>("vtmin").await?;
         debug!("vtmin: {} V", val);
         Ok(val)
@@ -328,14 +392,14 @@ where
 
     /// Sets the minimum TEC voltage in V.
     #[allow(non_snake_case)]
-    pub async fn set_tec_min_V(&mut self, volts: f32) -> Result<()> {
+    pub async fn set_tec_min_V(&mut self, volts: f32) -> Result<(), ErrorReport> {
         debug!("set vtmin: {} V", volts);
         self.set("vtmin", Value::Float(volts)).await
     }
 
     /// Returns the maximum TEC voltage in V.
     #[allow(non_snake_case)]
-    pub async fn tec_max_V(&mut self) -> Result<f32> {
+    pub async fn tec_max_V(&mut self) -> Result<f32, ErrorReport> {
         let val = self.get::<f32>("vtmax").await?;
         debug!("vtmax: {} V", val);
         Ok(val)
@@ -343,14 +407,14 @@ where
 
     /// Sets the maximum TEC voltage in V.
     #[allow(non_snake_case)]
-    pub async fn set_tec_max_V(&mut self, volts: f32) -> Result<()> {
+    pub async fn set_tec_max_V(&mut self, volts: f32) -> Result<(), ErrorReport> {
         debug!("set vtmax: {} V", volts);
         self.set("vtmax", Value::Float(volts)).await
     }
 
     /// Returns the temperature modulation gain in Ohms/V.
     #[allow(non_snake_case)]
-    pub async fn temp_mod_gain_Ohm_V(&mut self) -> Result<f32> {
+    pub async fn temp_mod_gain_Ohm_V(&mut self) -> Result<f32, ErrorReport> {
         let gain = self.get::<f32>("tmodgain").await?;
         debug!("tmodgain: {} Ohms/V", gain);
         Ok(gain)
@@ -358,14 +422,14 @@ where
 
     /// Sets the temperature modulation gain in Ohms/V.
     #[allow(non_snake_case)]
-    pub async fn set_temp_mod_gain_Ohm_V(&mut self, gain_ohm_V: f32) -> Result<()> {
+    pub async fn set_temp_mod_gain_Ohm_V(&mut self, gain_ohm_V: f32) -> Result<(), ErrorReport> {
         debug!("set tmodgain: {} Ohms/V", gain_ohm_V);
         self.set("tmodgain", Value::Float(gain_ohm_V)).await
     }
 
     /// Returns the photodiode current in mA.
     #[allow(non_snake_case)]
-    pub async fn pd_current_mA(&mut self) -> Result<f32> {
+    pub async fn pd_current_mA(&mut self) -> Result<f32, ErrorReport> {
         let current = self.get::<f32>("iphd").await?;
         debug!("iphd: {} mA", current);
         Ok(current)
@@ -373,7 +437,7 @@ where
 
     /// Returns the analog input 1 voltage in V.
     #[allow(non_snake_case)]
-    pub async fn ain_1_V(&mut self) -> Result<f32> {
+    pub async fn ain_1_V(&mut self) -> Result<f32, ErrorReport> {
         let volts = self.get::<f32>("ain1").await?;
         debug!("ain1: {} V", volts);
         Ok(volts)
@@ -381,7 +445,7 @@ where
 
     /// Returns the analog input 2 voltage in V.
     #[allow(non_snake_case)]
-    pub async fn ain_2_V(&mut self) -> Result<f32> {
+    pub async fn ain_2_V(&mut self) -> Result<f32, ErrorReport> {
         let volts = self.get::<f32>("ain2").await?;
         debug!("ain2: {} V", volts);
         Ok(volts)
@@ -389,29 +453,46 @@ where
 
     /// Returns the board temperature in C.
     #[allow(non_snake_case)]
-    pub async fn board_temp_C(&mut self) -> Result<f32> {
+    pub async fn board_temp_C(&mut self) -> Result<f32, ErrorReport> {
         let temp = self.get::<f32>("tboard").await?;
         debug!("tboard: {} C", temp);
         Ok(temp)
     }
 
-    // TODO(xguo): 128 isn't enough for board status. Let's find other ways to handle this.
-    // /// Returns a summary of the board status.
-    // pub async fn board_status(&mut self) -> Result<BoardStatus> {
-    //     let status = self.get::<BoardStatus>("status").await?;
-    //     debug!("status: {:?}", status);
-    //     Ok(status)
-    // }
+    /// Returns a summary of the board status.
+    ///
+    /// The dump does not fit into the driver buffer, so it is decoded while it streams in.
+    pub async fn board_status(&mut self) -> Result<BoardStatus, ErrorReport> {
+        self.send("status").await?;
+        let mut stream = StreamDeserializer::<_, STATUS_LINE_SIZE>::new(
+            self.reader.chain(&mut self.uart),
+            self.prompt,
+        );
+        if self.echo != EchoMode::Off {
+            let echo = stream
+                .next_line()
+                .await
+                .map_err(|e| e.with_device(DEVICE).with_command(b"status"))?;
+            strip_echo(self.echo, b"status", echo.unwrap_or_default())
+                .map_err(|e| report(e, "status"))?;
+        }
+        let status: BoardStatus = stream
+            .decode_lines::<_, MAX_STRING_SIZE>()
+            .await
+            .map_err(|e| e.with_device(DEVICE).with_command(b"status"))?;
+        debug!("status: {:?}", status);
+        Ok(status)
+    }
 
     /// Saves the current configuration to flash.
-    pub async fn save_config(&mut self) -> Result<()> {
+    pub async fn save_config(&mut self) -> Result<(), ErrorReport> {
         debug!("save");
         // TODO(xguo): I'm keeping in sync with varst here, though "save nothing" feels a bit odd.
         self.set("save", Value::None).await
     }
 
     /// Returns the serial number of the board.
-    pub async fn serial_number(&mut self) -> Result<&'_ [u8]> {
+    pub async fn serial_number(&mut self) -> Result<&'_ [u8], ErrorReport> {
         let serial = self.get::<&'_ [u8]>("serial").await?;
         debug!(
             "serial: {:?}",
@@ -421,7 +502,7 @@ where
     }
 
     /// Returns the user data string.
-    pub async fn userdata<'b>(&mut self) -> Result<&'_ [u8]> {
+    pub async fn userdata(&mut self) -> Result<&'_ [u8], ErrorReport> {
         let data = self.get::<&'_ [u8]>("userdata").await?;
         debug!(
             "userdata: {:?}",
@@ -430,10 +511,10 @@ where
         Ok(data)
     }
 
-    /// Sets the user data string.
-    pub async fn set_userdata(&mut self, data: &'_ [u8]) -> Result<()> {
-        if !data.is_ascii() || data.iter().any(|&b| b.is_ascii_whitespace()) {
-            return Err(Error::DeviceError);
+    /// Sets the user data string. Data containing whitespace is sent quoted.
+    pub async fn set_userdata(&mut self, data: &'_ [u8]) -> Result<(), ErrorReport> {
+        if !data.is_ascii() {
+            return Err(Error::DeviceError.into());
         }
         debug!(
             "userdata write: {:?}",
@@ -444,7 +525,7 @@ where
 
     /// Returns the baud rate of the board serial interface.
     #[allow(non_snake_case)]
-    pub async fn baud_rate_Hz(&mut self) -> Result<i32> {
+    pub async fn baud_rate_Hz(&mut self) -> Result<i32, ErrorReport> {
         let rate = self.get::<i32>("brate").await?;
         debug!("brate: {} Hz", rate);
         Ok(rate)
@@ -452,26 +533,26 @@ where
 
     /// Sets the baud rate of the board serial interface.
     #[allow(non_snake_case)]
-    pub async fn set_baud_rate_Hz(&mut self, rate_Hz: i32) -> Result<()> {
+    pub async fn set_baud_rate_Hz(&mut self, rate_Hz: i32) -> Result<(), ErrorReport> {
         debug!("set brate: {} Hz", rate_Hz);
         self.set("brate", Value::Int(rate_Hz)).await
     }
 
     /// Returns the error state of the board.
-    pub async fn err(&mut self) -> Result<i32> {
+    pub async fn err(&mut self) -> Result<i32, ErrorReport> {
         let errors = self.get::<i32>("err").await?;
         debug!("err: {}", errors);
         Ok(errors)
     }
 
     /// Clears the error state of the board.
-    pub async fn clear_err(&mut self) -> Result<()> {
+    pub async fn clear_err(&mut self) -> Result<(), ErrorReport> {
         debug!("clear err for CTL200");
         self.set("errclr", Value::None).await
     }
 
     /// Returns the firmware version.
-    pub async fn version(&mut self) -> Result<&[u8]> {
+    pub async fn version(&mut self) -> Result<&[u8], ErrorReport> {
         debug!("Ctl200::version() 0");
         let resp: &[u8] = self.get::<&[u8]>("version").await?;
         let t = core::str::from_utf8(resp).map_err(|_| Error::BytesToUTF8Error)?;
@@ -479,116 +560,110 @@ where
         Ok(resp)
     }
 
-    // TODO(xguo): Refactor the code to use ferox::uart.
-    async fn query(&mut self, request: &str) -> Result<&'_ [u8]> {
+    async fn send(&mut self, request: &str) -> Result<(), ErrorReport> {
         debug!("Sending command: '{}'", request);
-        self.uart.write_all(request.as_bytes()).await.map_err(|_| {
+        // Whatever arrived since the last response, e.g. after a timeout, would be taken for
+        // the response to this request.
+        self.reader.clear();
+        let stale = drain(&mut self.uart).await;
+        if stale > 0 {
+            debug!("Dropped {} stale bytes", stale);
+        }
+        self.uart.write_all(request.as_bytes()).await.map_err(|e| {
             debug!("Failed to write command");
-            Error::WriteErrorInCtl200Query
+            report(Error::WriteErrorInCtl200Query, request).with_io(e.kind())
         })?;
-        self.uart.write_all(CRLF).await.map_err(|_| {
-            debug!("Failed to write CRLF");
-            Error::WriteErrorInCtl200Query
+        self.uart.write_all(self.line_ending).await.map_err(|e| {
+            debug!("Failed to write line ending");
+            report(Error::WriteErrorInCtl200Query, request).with_io(e.kind())
         })?;
-        self.uart.flush().await.map_err(|_| {
+        self.uart.flush().await.map_err(|e| {
             debug!("Failed to flush UART");
-            Error::FlushError
-        })?;
-
-        debug!("Waiting for response...");
-        let full_response = self.read_until(CRLF_PROMPT).await?;
-        debug!("Got response: {:?}", full_response);
-
-        let mut echo_end = None;
-        let mut response_start = None;
-        let len = full_response.len();
+            report(Error::FlushError, request).with_io(e.kind())
+        })
+    }
+
+    /// Sends a blank line, waits for the prompt and drops anything else the board sends.
+    /// Brings the link back to a known state after garbage.
+    pub async fn resync(&mut self) -> Result<(), ErrorReport> {
+        debug!("Resynchronizing CTL200");
+        self.send("").await?;
+        self.reader.read_frame(&mut self.uart, self.prompt).await?;
+        self.reader.clear();
+        drain(&mut self.uart).await;
+        Ok(())
+    }
 
-        for i in 0..len - 2 {
-            if full_response[i] == b'\r' && full_response[i + 1] == b'\n' {
-                if echo_end.is_none() {
-                    echo_end = Some(i);
-                    response_start = Some(i + 2);
-                } else {
-                    return Err(Error::InvalidResponse);
+    // TODO(xguo): Refactor the code to use ferox::uart.
+    async fn query(&mut self, request: &str) -> Result<&'_ [u8], ErrorReport> {
+        match self.exchange(request).await {
+            Ok(len) => Ok(&self.buf[..len]),
+            Err(e) => {
+                if matches!(e.error, Error::EchoMismatch | Error::InvalidResponse) {
+                    if let Err(e) = self.resync().await {
+                        debug!("Resync failed: {:?}", e);
+                    }
                 }
+                Err(e)
             }
         }
-
-        let (echo, response) = match (echo_end, response_start) {
-            (Some(e_end), Some(r_start)) => {
-                let echo = &full_response[..e_end];
-                let response = &full_response[r_start..];
-                (echo, response)
-            }
-            _ => return Err(Error::InvalidResponse),
-        };
-        debug!("Got echo: {:?}, response: {:?}", echo, response);
-
-        if echo != request.as_bytes() {
-            info!(
-                "Echo mismatch: expected {}, got {}",
-                request,
-                core::str::from_utf8(echo).unwrap_or("<invalid>")
-            );
-            return Err(Error::EchoMismatch);
-        }
-
-        Ok(response)
     }
 
-    // TODO(xguo): Implement a more efficient version of this function
-    async fn read_until(&mut self, expected_str: &[u8]) -> Result<&'_ [u8]> {
-        let mut byte = [0u8; 1];
-
-        while self.buf_pos + expected_str.len() <= MAX_STRING_SIZE {
-            // Read one byte
-            self.uart
-                .read(&mut byte)
-                .await
-                .map_err(|_| Error::ReadError)?;
+    // Sends `request`, copies its response into `buf` and returns its length.
+    async fn exchange(&mut self, request: &str) -> Result<usize, ErrorReport> {
+        self.send(request).await?;
 
-            // Add to buffer
-            self.buf[self.buf_pos] = byte[0];
-            self.buf_pos += 1;
-
-            // Check if we have enough bytes to match expected_str
-            if self.buf_pos >= expected_str.len() {
-                // Check if buffer ends with expected_str
-                let start_idx = self.buf_pos - expected_str.len();
-                if self.buf[start_idx..self.buf_pos] == expected_str[..] {
-                    // Found match, prepare result
-                    let result = &self.buf[..start_idx];
-
-                    // Reset buffer position to allow future reads
-                    self.buf_pos = 0;
-
-                    return Ok(result);
-                }
-            }
-        }
+        debug!("Waiting for response...");
+        let full_response = self
+            .reader
+            .read_frame(&mut self.uart, self.prompt)
+            .await
+            .map_err(|e| e.with_device(DEVICE).with_command(request.as_bytes()))?;
+        debug!("Got response: {:?}", full_response);
 
-        // Buffer overflow
-        Err(Error::BufferOverflow)
+        let response = strip_echo(self.echo, request.as_bytes(), full_response).map_err(|e| {
+            info!("Echo mismatch for {}", request);
+            report(e, request)
+        })?;
+        debug!("Got response without echo: {:?}", response);
+        self.buf[..response.len()].copy_from_slice(response);
+        Ok(response.len())
     }
 
-    async fn get<'b, T>(&'b mut self, param: &str) -> Result<T>
+    async fn get<'b, T>(&'b mut self, param: &str) -> Result<T, ErrorReport>
     where
         T: FromBytes<'b>,
     {
         let response = self.query(param).await?;
-        T::from_bytes(response)
+        T::from_bytes(response).map_err(|e| report(e, param))
     }
 
     #[allow(dead_code)]
-    async fn set<'b>(&mut self, param: &str, value: Value<'b>) -> Result<()> {
-        use core::fmt::Write;
-        let mut s: String<MAX_STRING_SIZE> = String::new();
-        write!(&mut s, "{} {}", param, value).unwrap();
-        let _ = self.query(&s).await?;
+    async fn set<'b>(&mut self, param: &str, value: Value<'b>) -> Result<(), ErrorReport> {
+        let mut request = [0u8; MAX_STRING_SIZE];
+        // The command name goes out as is, only the value may need quoting.
+        let start = param.len() + 1;
+        if start > request.len() {
+            return Err(report(Error::BufferOverflow, param));
+        }
+        request[..param.len()].copy_from_slice(param.as_bytes());
+        request[param.len()] = b' ';
+        let len = to_slice_with_format(&value, &mut request[start..], self.float_format)
+            .map_err(|e| report(e, param))?;
+        let request = from_utf8(&request[..start + len])
+            .map_err(|_| report(Error::BytesToUTF8Error, param))?;
+        let _ = self.query(request).await?;
         Ok(())
     }
 }
 
+// A failure of `request` on the CTL200.
+fn report(error: Error, request: &str) -> ErrorReport {
+    ErrorReport::new(error)
+        .with_device(DEVICE)
+        .with_command(request.as_bytes())
+}
+
 pub enum ParseError {
     ParseError,
 }
@@ -616,13 +691,38 @@ pub enum Value<'a> {
     None,
 }
 
+// Written as the argument of a command, e.g. `1` or `"hello world"`.
+impl Serialize for Value<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        match self {
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(i) => serializer.serialize_i32(*i),
+            Value::Float(fl) => serializer.serialize_f32(*fl),
+            Value::String(s) => serializer.serialize_str(
+                from_utf8(s).map_err(|_| serde::ser::Error::custom("invalid utf8"))?,
+            ),
+            Value::None => serializer.serialize_str("None"),
+        }
+    }
+}
+
 impl Display for Value<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Bool(b) => write!(f, "{}", if *b { "1" } else { "0" }),
             Value::Int(i) => write!(f, "{}", i),
             Value::Float(fl) => write!(f, "{}", fl),
-            Value::String(s) => write!(f, "{}", core::str::from_utf8(s).map_err(|_| fmt::Error)?),
+            Value::String(s) => {
+                let s = core::str::from_utf8(s).map_err(|_| fmt::Error)?;
+                if needs_quoting(s) {
+                    write_quoted(f, s)
+                } else {
+                    write!(f, "{}", s)
+                }
+            }
             Value::None => write!(f, "None"),
         }
     }
@@ -637,15 +737,28 @@ mod tests {
     use futures::lock::Mutex;
 
     use super::*;
-    use crate::testing::helpers::init_logger;
+    use crate::{
+        testing::helpers::init_logger,
+        uart::{post_processor::DefaultPostProcessor, UartWrapper},
+    };
 
     const UNKNOWN_COMMAND: &[u8] = b"Unknown command";
+    const CRLF: &[u8] = b"\r\n";
+    const CRLF_PROMPT: &[u8] = b"\r\n>>";
 
     lazy_static::lazy_static! {
         static ref COMMAND_MAP: Mutex<HashMap<&'static str, StdString>> = {
             let mut m = HashMap::new();
             m.insert("version", StdString::from("V0.17"));
             m.insert("lason", StdString::from("0"));
+            m.insert("rtset", StdString::from("10000"));
+            m.insert("userdata", StdString::new());
+            let mut status = StdString::from("lason 1\r\nilaser: 12.5\r\nrtact 10000.0\r\n");
+            for i in 0..32 {
+                status.push_str(&std::format!("reserved{} {}\r\n", i, i));
+            }
+            status.push_str("err 0x4");
+            m.insert("status", status);
             // Add more commands as needed
             Mutex::new(m)
         };
@@ -666,6 +779,10 @@ mod tests {
     struct MockStream {
         read_data: Arc<Mutex<Vec<u8>>>,
         write_data: Arc<Mutex<Vec<u8>>>,
+        // Every command written to the mock, without its line ending.
+        commands: Arc<Mutex<Vec<StdString>>>,
+        // What ends each command written to the mock.
+        line_ending: &'static [u8],
     }
 
     impl embedded_io::ErrorType for MockStream {
@@ -677,9 +794,16 @@ mod tests {
             MockStream {
                 read_data: Arc::new(Mutex::new(Vec::new())),
                 write_data: Arc::new(Mutex::new(Vec::new())),
+                commands: Arc::new(Mutex::new(Vec::new())),
+                line_ending: CRLF,
             }
         }
 
+        fn with_line_ending(mut self, line_ending: &'static [u8]) -> Self {
+            self.line_ending = line_ending;
+            self
+        }
+
         fn append_read_data(&self, data: &[u8]) {
             let mut read_data = futures::executor::block_on(self.read_data.lock());
             println!("Appending read data: {:?}", data);
@@ -702,20 +826,33 @@ mod tests {
             let mut data = self.write_data.lock().await;
             data.extend_from_slice(buf);
 
-            // Check if the data ends with CRLF (Carriage Return and Line Feed)
-            if data.ends_with(CRLF) {
-                // Extract the command from the data, excluding the CRLF
-                let command = StdString::from_utf8(data[..data.len() - 2].to_vec()).unwrap();
+            // Check if the data ends with the line ending
+            if data.ends_with(self.line_ending) {
+                // Extract the command from the data, excluding the line ending
+                let end = data.len() - self.line_ending.len();
+                let command = StdString::from_utf8(data[..end].to_vec()).unwrap();
                 debug!("Received command: {}", command);
 
                 // Append the command and CRLF to the read data
                 self.append_read_data(command.as_bytes());
                 self.append_read_data(CRLF);
                 data.clear();
+                self.commands.lock().await.push(command.clone());
+
+                // The data may be quoted and contain spaces, so it is not split.
+                if let Some(userdata) = command.strip_prefix("userdata write ") {
+                    let mut map = COMMAND_MAP.lock().await;
+                    *map.get_mut("userdata").unwrap() = StdString::from(userdata);
+                    self.append_read_data(userdata.as_bytes());
+                    self.append_read_data(CRLF_PROMPT);
+                    return Ok(buf.len());
+                }
 
                 let cmds: Vec<StdString> =
                     command.split_whitespace().map(StdString::from).collect();
                 match cmds.len() {
+                    // A blank line only gets the prompt.
+                    0 => {}
                     1 => {
                         // GET command
                         if let Some(response) = COMMAND_MAP.lock().await.get(cmds[0].as_str()) {
@@ -756,6 +893,25 @@ mod tests {
         assert_eq!(result, b"V0.17");
     }
 
+    #[tokio::test]
+    async fn test_ctl200_over_uart_wrapper() {
+        init_logger();
+        let uart = UartWrapper::new(MockStream::new(), DefaultPostProcessor);
+        let mut ctl200 = Ctl200::new(uart);
+        assert_eq!(ctl200.get::<&[u8]>("version").await.unwrap(), b"V0.17");
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_protocol_line_ending() {
+        init_logger();
+        let mock_stream = MockStream::new().with_line_ending(b"\r");
+        let protocol = protocol::ctl200().with_line_ending(b"\r");
+        let mut ctl200 = Ctl200::with_protocol(mock_stream.clone(), protocol);
+        assert_eq!(ctl200.get::<&[u8]>("version").await.unwrap(), b"V0.17");
+        ctl200.resync().await.unwrap();
+        assert!(mock_stream.read_data.lock().await.is_empty());
+    }
+
     #[tokio::test]
     async fn test_ctl200_set() {
         init_logger();
@@ -770,4 +926,86 @@ mod tests {
         debug!(">>>Getting lason as true");
         assert!(ctl200.get::<bool>("lason").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn test_ctl200_float_format() {
+        init_logger();
+        let mut ctl200 = Ctl200::new(MockStream::new()).with_float_format(FloatFormat::fixed(1));
+        ctl200.set_temp_set_Ohm(9876.54).await.unwrap();
+        assert_eq!(ctl200.get::<&[u8]>("rtset").await.unwrap(), b"9876.5");
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_error_report() {
+        init_logger();
+        let mut ctl200 = Ctl200::new(MockStream::new());
+        // The mock fails to write commands with more than one argument.
+        let report = ctl200.query("rtset 1 2").await.unwrap_err();
+        assert_eq!(report.error, Error::WriteErrorInCtl200Query);
+        assert_eq!(report.io, Some(embedded_io::ErrorKind::Other));
+        assert_eq!(report.device, Some("ctl200"));
+        assert_eq!(report.command.unwrap(), "rtset");
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_set_userdata() {
+        init_logger();
+        let mock_stream = MockStream::new();
+        let mut ctl200 = Ctl200::new(mock_stream.clone());
+        ctl200.set_userdata(b"hello world").await.unwrap();
+        assert_eq!(
+            mock_stream.commands.lock().await.as_slice(),
+            ["userdata write \"hello world\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_drops_stale_input() {
+        init_logger();
+        let mock_stream = MockStream::new();
+        // The late response to an earlier request.
+        mock_stream.append_read_data(b"lason\r\n0\r\n>>");
+        let mut ctl200 = Ctl200::new(mock_stream);
+        assert_eq!(ctl200.version().await.unwrap(), b"V0.17");
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_resync() {
+        init_logger();
+        let mock_stream = MockStream::new();
+        let mut ctl200 = Ctl200::new(mock_stream.clone());
+        ctl200.resync().await.unwrap();
+        assert!(futures::executor::block_on(mock_stream.read_data.lock()).is_empty());
+        assert_eq!(ctl200.version().await.unwrap(), b"V0.17");
+    }
+
+    #[tokio::test]
+    async fn test_ctl200_board_status() {
+        init_logger();
+        let mut ctl200 = Ctl200::new(MockStream::new());
+        let status = ctl200.board_status().await.unwrap();
+        assert_eq!(
+            status,
+            BoardStatus {
+                lason: Some(true),
+                ilaser: Some(12.5),
+                rtact: Some(10000.0),
+                err: Some(Hex(4)),
+                ..Default::default()
+            }
+        );
+        // The driver is still usable after the streamed response.
+        assert_eq!(ctl200.get::<&[u8]>("version").await.unwrap(), b"V0.17");
+    }
+
+    #[test]
+    fn test_value_string_quoting() {
+        use std::format;
+
+        assert_eq!(format!("{}", Value::String(b"abc")), "abc");
+        assert_eq!(
+            format!("{}", Value::String(b"hello \"world\"")),
+            r#""hello \"world\"""#
+        );
+    }
 }
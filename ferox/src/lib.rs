@@ -1,5 +1,8 @@
 #![no_std]
 
+// Lets code generated by `ferox-derive` name this crate as `::ferox` from inside it, too.
+extern crate self as ferox;
+
 pub mod drivers;
 pub mod proto;
 pub mod uart;
@@ -1,5 +1,7 @@
 pub mod ascii;
+pub mod binary;
+pub mod command;
 pub mod error;
 pub mod ferox;
 
-pub type Result<T> = core::result::Result<T, error::Error>;
+pub type Result<T, E = error::Error> = core::result::Result<T, E>;
@@ -1,28 +1,91 @@
+use embedded_io_async::Write;
 use heapless::Vec;
+use postcard::ser_flavors::{Flavor, Slice};
 use serde::{Deserialize, Serialize};
 
+use self::float::FloatFormat;
 use crate::{
-    proto::{error::Error as FeroxError, Result},
+    proto::{
+        command::Commands,
+        error::{Error as FeroxError, ErrorReport},
+        Result,
+    },
     MAX_STRING_SIZE,
 };
 
 pub mod deser;
+pub mod float;
+pub mod hex;
+pub mod number;
+pub mod quote;
 pub mod ser;
-pub mod vec;
+pub mod stream;
 
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8, MAX_STRING_SIZE>>
 where
     T: Serialize,
 {
-    let mut serializer = ser::AsciiSerializer::new(vec::FeroxVec::<MAX_STRING_SIZE>::new());
+    to_bytes_with_format(value, FloatFormat::default())
+}
+
+/// Like [`to_bytes`], writing floats in `format`.
+pub fn to_bytes_with_format<T>(value: &T, format: FloatFormat) -> Result<Vec<u8, MAX_STRING_SIZE>>
+where
+    T: Serialize,
+{
+    let mut bytes = Vec::new();
+    // Cannot fail, the capacity is `MAX_STRING_SIZE`.
+    let _ = bytes.resize(MAX_STRING_SIZE, 0);
+    let len = to_slice_with_format(value, &mut bytes, format)?;
+    bytes.truncate(len);
+    Ok(bytes)
+}
+
+/// Serializes `value` into `buf` and returns the number of bytes used.
+pub fn to_slice<T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where
+    T: Serialize + ?Sized,
+{
+    to_slice_with_format(value, buf, FloatFormat::default())
+}
+
+/// Like [`to_slice`], writing floats in `format`.
+pub fn to_slice_with_format<T>(value: &T, buf: &mut [u8], format: FloatFormat) -> Result<usize>
+where
+    T: Serialize + ?Sized,
+{
+    let mut serializer = ser::AsciiSerializer::new(Slice::new(buf)).with_float_format(format);
     value.serialize(&mut serializer)?;
-    let t = serializer
+    let used = serializer
+        .finalize()
         .finalize()
-        .release()
         .map_err(|_| FeroxError::BufferOverflow)?;
-    Ok(t)
+    Ok(used.len())
 }
 
+/// Serializes `value` into `buf` once, then writes it to `writer`, and returns the number of
+/// bytes written.
+///
+/// Nothing is written if `value` cannot be serialized or does not fit into `buf`. The writer is
+/// not flushed.
+pub async fn to_writer<T, W>(
+    value: &T,
+    writer: &mut W,
+    buf: &mut [u8],
+) -> Result<usize, ErrorReport>
+where
+    T: Serialize + ?Sized,
+    W: Write,
+{
+    let len = to_slice(value, buf)?;
+    writer
+        .write_all(&buf[..len])
+        .await
+        .map_err(|e| ErrorReport::from_io(FeroxError::WriteError, e))?;
+    Ok(len)
+}
+
+/// Decodes `bytes`, ignoring anything left after the value, e.g. a unit after a number.
 pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T>
 where
     T: Deserialize<'de>,
@@ -31,3 +94,158 @@ where
     let t = T::deserialize(&mut de)?;
     Ok(t)
 }
+
+/// Like [`from_bytes`], but fails with [`FeroxError::TrailingCharacters`] unless the whole input
+/// is used, e.g. for a bounded sequence or tuple that must have exactly its length.
+pub fn from_bytes_strict<'de, T>(bytes: &'de [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    Ok(from_bytes_with_context(bytes)?)
+}
+
+/// Like [`from_bytes_strict`], but on failure also reports where in `bytes` decoding stopped.
+pub fn from_bytes_with_context<'de, T>(
+    bytes: &'de [u8],
+) -> core::result::Result<T, deser::DeserializeError>
+where
+    T: Deserialize<'de>,
+{
+    decode(deser::AsciiDeserializer::new(bytes))
+}
+
+/// Like [`from_bytes_with_context`], but the command name may be typed in any case, be an alias,
+/// or be an unambiguous prefix, see [`resolve`](crate::proto::command::resolve).
+pub fn from_bytes_relaxed<'de, T>(
+    bytes: &'de [u8],
+) -> core::result::Result<T, deser::DeserializeError>
+where
+    T: Deserialize<'de> + Commands,
+{
+    decode(deser::AsciiDeserializer::new(bytes).with_commands(T::COMMANDS))
+}
+
+// Decodes the whole input, so trailing tokens are an error.
+fn decode<'de, T>(
+    mut de: deser::AsciiDeserializer<'de>,
+) -> core::result::Result<T, deser::DeserializeError>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(&mut de)
+        .and_then(|t| de.end().map(|_| t).map_err(Into::into))
+        .map_err(|e| {
+            let mut context = de.error_context();
+            context.message = e.message;
+            deser::DeserializeError {
+                error: e.error,
+                context,
+            }
+        })
+}
+
+/// Decodes a multi-line `key: value` or `key value` dump, such as a device status, into a
+/// struct or map. See [`deser::Layout::Lines`].
+pub fn from_lines<'de, T>(bytes: &'de [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    let mut de = deser::AsciiDeserializer::new(bytes).with_layout(deser::Layout::Lines);
+    Ok(T::deserialize(&mut de)?)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec as StdVec;
+
+    use serde::Serialize;
+
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    #[derive(Serialize)]
+    enum TestReq<'a> {
+        #[serde(rename = "varint")]
+        VarInt(Option<i32>),
+
+        #[serde(rename = "label")]
+        Label(&'a str),
+    }
+
+    // Accepts at most three bytes per write.
+    #[derive(Default)]
+    struct SlowWriter {
+        data: StdVec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for SlowWriter {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for SlowWriter {
+        async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Self::Error> {
+            let n = buf.len().min(3);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_to_slice() {
+        init_logger();
+        let mut buf = [0u8; 16];
+        let len = to_slice(&TestReq::VarInt(Some(42)), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"varint 42");
+
+        let mut small = [0u8; 4];
+        assert_eq!(
+            FeroxError::BufferOverflow,
+            to_slice(&TestReq::VarInt(Some(42)), &mut small).unwrap_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_to_writer() {
+        init_logger();
+        let mut buf = [0u8; 16];
+        let mut writer = SlowWriter::default();
+        assert_eq!(
+            to_writer(&TestReq::VarInt(None), &mut writer, &mut buf).await,
+            Ok(7)
+        );
+        assert_eq!(writer.data, b"varint?");
+
+        // Longer than MAX_STRING_SIZE, as the caller picks the buffer.
+        let label = "long label ".repeat(40);
+        let mut buf = [0u8; 512];
+        let mut writer = SlowWriter::default();
+        let written = to_writer(&TestReq::Label(&label), &mut writer, &mut buf)
+            .await
+            .unwrap();
+        assert!(written > MAX_STRING_SIZE);
+        assert_eq!(writer.data, std::format!("label \"{}\"", label).as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_to_writer_error_writes_nothing() {
+        init_logger();
+        let mut buf = [0u8; 4];
+        let mut writer = SlowWriter::default();
+        assert_eq!(
+            to_writer(&(), &mut writer, &mut buf)
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::NotSupportedInSerializing
+        );
+        assert_eq!(
+            to_writer(&TestReq::VarInt(Some(42)), &mut writer, &mut buf)
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::BufferOverflow
+        );
+        assert!(writer.data.is_empty());
+    }
+}
@@ -1,18 +1,277 @@
+use core::fmt::{self, Display};
+
 use defmt_or_log::debug;
+use heapless::String;
 use serde::de::{
-    DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, VariantAccess, Visitor,
+    self, value::BorrowedStrDeserializer, DeserializeSeed, Deserializer, EnumAccess,
+    IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+
+use super::{
+    number::{self, Float},
+    quote::{quoted_len, unquote, Unquoted},
+};
+use crate::proto::{
+    command::{resolve, CommandInfo},
+    error::Error as FeroxError,
+    Result,
 };
 
-use crate::proto::{error::Error as FeroxError, Result};
+/// How structs and maps are laid out in the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Layout {
+    /// Struct fields are space-separated tokens in declaration order, e.g. `1.5 2.5`, and maps
+    /// are space-separated `key=value` pairs.
+    Positional,
+    /// One `key: value` or `key value` line per field, in any order, as in device status dumps.
+    /// Keys have no whitespace, values may contain `:`. Unknown keys are skipped and missing
+    /// `Option` fields are `None`.
+    Lines,
+}
+
+/// What the deserializer was looking for when it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Expected {
+    Number,
+    Bool,
+    String,
+    VariantName,
+    /// An argument after the command name.
+    Argument,
+    /// The `key` of a `key=value` pair.
+    Key,
+    /// No more input.
+    End,
+}
+
+impl Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Expected::Number => "number",
+            Expected::Bool => "bool",
+            Expected::String => "string",
+            Expected::VariantName => "variant name",
+            Expected::Argument => "argument",
+            Expected::Key => "key",
+            Expected::End => "end of input",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Longest part of the offending token kept in an [`ErrorContext`].
+pub const CONTEXT_TOKEN_SIZE: usize = 16;
+
+/// Longest part of an error message kept in a [`DeError`] or an [`ErrorContext`].
+pub const MESSAGE_SIZE: usize = 40;
+
+/// Where decoding failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorContext {
+    /// Byte offset of the offending token in the input.
+    pub offset: usize,
+    /// The offending token, truncated to [`CONTEXT_TOKEN_SIZE`] bytes. Empty at end of input.
+    pub token: String<CONTEXT_TOKEN_SIZE>,
+    /// What was expected instead, if known.
+    pub expected: Option<Expected>,
+    /// The message of the type being decoded, e.g. ``missing field `ilaser` ``, truncated to
+    /// [`MESSAGE_SIZE`] bytes. Empty if there is none.
+    pub message: String<MESSAGE_SIZE>,
+}
+
+impl ErrorContext {
+    fn new(offset: usize, token: &[u8], expected: Option<Expected>) -> Self {
+        let token = &token[..token.len().min(CONTEXT_TOKEN_SIZE)];
+        let valid = match core::str::from_utf8(token) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&token[..e.valid_up_to()]).unwrap_or_default(),
+        };
+        Self {
+            offset,
+            token: String::try_from(valid).unwrap_or_default(),
+            expected,
+            message: String::new(),
+        }
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "at byte {} (end of input)", self.offset)?;
+        } else {
+            write!(f, "at byte {} near {:?}", self.offset, self.token.as_str())?;
+        }
+        if let Some(expected) = self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// A deserialization error together with where it happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeserializeError {
+    pub error: FeroxError,
+    pub context: ErrorContext,
+}
+
+impl Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.error, self.context)
+    }
+}
+
+impl From<DeserializeError> for FeroxError {
+    fn from(e: DeserializeError) -> Self {
+        e.error
+    }
+}
+
+/// The error of an [`AsciiDeserializer`]. Unlike a bare [`FeroxError`] it keeps the message of
+/// errors raised by the type being decoded, such as a missing struct field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeError {
+    pub error: FeroxError,
+    /// Truncated to [`MESSAGE_SIZE`] bytes. Empty if there is none.
+    pub message: String<MESSAGE_SIZE>,
+}
+
+impl From<FeroxError> for DeError {
+    fn from(error: FeroxError) -> Self {
+        Self {
+            error,
+            message: String::new(),
+        }
+    }
+}
+
+impl From<DeError> for FeroxError {
+    fn from(e: DeError) -> Self {
+        e.error
+    }
+}
+
+impl Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}: {}", self.error, self.message)
+        }
+    }
+}
+
+impl core::error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: Display,
+    {
+        let mut message = String::new();
+        // What does not fit is cut off.
+        let _ = fmt::write(&mut Truncating(&mut message), format_args!("{}", msg));
+        Self {
+            error: FeroxError::InvalidRequestForDeserialize,
+            message,
+        }
+    }
+
+    // Raised by bounded containers such as `heapless::Vec` when there are more elements than fit.
+    fn invalid_length(len: usize, exp: &dyn de::Expected) -> Self {
+        let e = Self::custom(format_args!("invalid length {}, expected {}", len, exp));
+        Self {
+            error: FeroxError::InvalidLength,
+            ..e
+        }
+    }
+}
+
+// Writes into a string as far as it fits.
+struct Truncating<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> fmt::Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.push(c).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
 
 pub struct AsciiDeserializer<'de> {
     input: &'de [u8],
     index: usize,
+    layout: Layout,
+    // Byte range of the last token read, used to locate errors.
+    last: (usize, usize),
+    // Context of the innermost error, recorded where it was raised.
+    context: Option<ErrorContext>,
+    // Command table used to match the first variant name loosely.
+    commands: Option<&'static [CommandInfo]>,
 }
 
 impl<'de> AsciiDeserializer<'de> {
     pub fn new(input: &'de [u8]) -> Self {
-        AsciiDeserializer { input, index: 0 }
+        AsciiDeserializer {
+            input,
+            index: 0,
+            layout: Layout::Positional,
+            last: (0, 0),
+            context: None,
+            commands: None,
+        }
+    }
+
+    /// Sets how structs and maps are laid out. Values nested inside a line are positional.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Matches the command name, i.e. the first variant name, against `commands` with
+    /// [`resolve`]: ignoring case, by alias, or by an unambiguous prefix.
+    pub fn with_commands(mut self, commands: &'static [CommandInfo]) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Checks that only whitespace is left in the input.
+    pub fn end(&mut self) -> Result<()> {
+        if self.next_token().is_some() {
+            return Err(self.error(FeroxError::TrailingCharacters, Expected::End));
+        }
+        Ok(())
+    }
+
+    /// Returns where the last error happened. Errors raised by visitors, such as a missing
+    /// struct field, are located at the last token read.
+    pub fn error_context(&self) -> ErrorContext {
+        self.context.clone().unwrap_or_else(|| {
+            ErrorContext::new(self.last.0, &self.input[self.last.0..self.last.1], None)
+        })
+    }
+
+    // Records the innermost error at the last token read.
+    fn error(&mut self, error: FeroxError, expected: Expected) -> FeroxError {
+        if self.context.is_none() {
+            let (start, end) = self.last;
+            self.context = Some(ErrorContext::new(
+                start,
+                &self.input[start..end],
+                Some(expected),
+            ));
+        }
+        error
     }
 
     fn skip_whitespace(&mut self) {
@@ -31,7 +290,13 @@ impl<'de> AsciiDeserializer<'de> {
         Some(remaining)
     }
 
-    fn next_token(&mut self) -> Option<&'de [u8]> {
+    pub(crate) fn next_token(&mut self) -> Option<&'de [u8]> {
+        let token = self.scan_token();
+        self.last = (self.index - token.map_or(0, <[u8]>::len), self.index);
+        token
+    }
+
+    fn scan_token(&mut self) -> Option<&'de [u8]> {
         self.skip_whitespace();
         if self.index >= self.input.len() {
             return None;
@@ -39,6 +304,12 @@ impl<'de> AsciiDeserializer<'de> {
 
         let start = self.index;
 
+        if self.input[start] == b'"' {
+            let rest = &self.input[start..];
+            self.index += quoted_len(rest).unwrap_or(rest.len());
+            return Some(&self.input[start..self.index]);
+        }
+
         while self.index < self.input.len() {
             let current = self.input[self.index];
 
@@ -61,235 +332,382 @@ impl<'de> AsciiDeserializer<'de> {
         Some(&self.input[start..self.index])
     }
 
+    fn next_str(&mut self) -> Result<&'de str> {
+        utf8(self.next_token().ok_or(FeroxError::EndOfFile)?)
+    }
+
+    // Integers are parsed at full width first, so that a well-formed number which does not fit
+    // into `T` is reported as `IntegerOverflow` rather than as a parse error.
+    fn parse_int<T: TryFrom<i128>>(&mut self) -> Result<T> {
+        self.next_str()
+            .and_then(number::parse_int)
+            .and_then(|wide| T::try_from(wide).map_err(|_| FeroxError::IntegerOverflow))
+            .map_err(|e| self.error(e, Expected::Number))
+    }
+
+    fn parse_float<T: Float>(&mut self) -> Result<T> {
+        self.next_str()
+            .and_then(number::parse_float)
+            .map_err(|e| self.error(e, Expected::Number))
+    }
+
     fn peek_token(&mut self) -> Option<&'de [u8]> {
-        let saved = self.index;
+        let (index, last) = (self.index, self.last);
         let t = self.next_token();
-        self.index = saved;
+        (self.index, self.last) = (index, last);
         t
     }
+
+    // Reads the next non-empty line and splits it into key and value. The key ends at the first
+    // `:` if there is one, otherwise at the first whitespace.
+    fn next_line(&mut self) -> Option<(&'de [u8], &'de [u8])> {
+        while self.index < self.input.len() {
+            let rest = &self.input[self.index..];
+            let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            self.index += (len + 1).min(rest.len());
+
+            let line = trim(&rest[..len]);
+            if line.is_empty() {
+                continue;
+            }
+            return Some(split_key(line));
+        }
+        None
+    }
+
+    // Reads the `key` of a `key=value` pair and consumes the `=`.
+    fn next_key(&mut self) -> Result<Option<&'de [u8]>> {
+        self.skip_whitespace();
+        if self.index >= self.input.len() {
+            return Ok(None);
+        }
+
+        let start = self.index;
+        while self.index < self.input.len() {
+            let current = self.input[self.index];
+            if current == b'=' {
+                let key = &self.input[start..self.index];
+                self.index += 1;
+                return Ok(Some(key));
+            }
+            if is_whitespace(current) {
+                break;
+            }
+            self.index += 1;
+        }
+
+        self.last = (start, self.index);
+        Err(self.error(FeroxError::UnexpectedToken, Expected::Key))
+    }
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str> {
+    core::str::from_utf8(bytes).map_err(|_| FeroxError::Utf8Error)
 }
 
 fn is_whitespace(b: u8) -> bool {
     matches!(b, b' ' | b'\t' | b'\r' | b'\n')
 }
 
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = bytes {
+        if !is_whitespace(*first) {
+            break;
+        }
+        bytes = rest;
+    }
+    while let [rest @ .., last] = bytes {
+        if !is_whitespace(*last) {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+/// Splits a trimmed line of [`Layout::Lines`] into its key and value. The key ends at a `:` that
+/// comes before any whitespace, or else at the first whitespace, so a value may contain `:`.
+pub(super) fn split_key(line: &[u8]) -> (&[u8], &[u8]) {
+    let space = line.iter().position(|&b| is_whitespace(b));
+    let colon = line[..space.unwrap_or(line.len())]
+        .iter()
+        .position(|&b| b == b':');
+    match colon.or(space) {
+        Some(i) => (trim(&line[..i]), trim(&line[i + 1..])),
+        None => (line, &line[line.len()..]),
+    }
+}
+
 impl<'de> Deserializer<'de> for &mut AsciiDeserializer<'de> {
-    type Error = FeroxError;
+    type Error = DeError;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        let token = self.next_token().ok_or(FeroxError::EndOfFile)?;
-        match token {
-            b"1" => visitor.visit_bool(true),
-            b"0" => visitor.visit_bool(false),
-            _ => Err(FeroxError::InvalidBoolean),
+        match self.next_token() {
+            Some(b"1") => visitor.visit_bool(true),
+            Some(b"0") => visitor.visit_bool(false),
+            Some(_) => Err(self
+                .error(FeroxError::InvalidBoolean, Expected::Bool)
+                .into()),
+            None => Err(self.error(FeroxError::EndOfFile, Expected::Bool).into()),
         }
     }
 
-    fn deserialize_i8<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_i8(self.parse_int()?)
     }
 
-    fn deserialize_i16<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_i16(self.parse_int()?)
     }
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        let token = self.next_token().ok_or(FeroxError::EndOfFile)?;
-        let s = core::str::from_utf8(token).map_err(|_| FeroxError::Utf8Error)?;
-        let parsed = s.parse::<i32>().map_err(|_| FeroxError::ParseIntError)?;
-        visitor.visit_i32(parsed)
+        visitor.visit_i32(self.parse_int()?)
     }
 
-    fn deserialize_i64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_i64(self.parse_int()?)
     }
 
-    fn deserialize_u8<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_u8(self.parse_int()?)
     }
 
-    fn deserialize_u16<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_u16(self.parse_int()?)
     }
 
-    fn deserialize_u32<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_u32(self.parse_int()?)
     }
 
-    fn deserialize_u64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_u64(self.parse_int()?)
     }
 
-    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        let token = self.next_token().ok_or(FeroxError::EndOfFile)?;
-        let s = core::str::from_utf8(token).map_err(|_| FeroxError::Utf8Error)?;
-        let parsed = s.parse::<f32>().map_err(|_| FeroxError::ParseFloatError)?;
-        visitor.visit_f32(parsed)
+        visitor.visit_f32(self.parse_float()?)
     }
 
-    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_f64(self.parse_float()?)
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_str<V>(self, _visitor: V) -> Result<V::Value>
+    // Escaped strings cannot be borrowed, so they need an owned target such as `heapless::String`.
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        let token = self.next_token().ok_or(FeroxError::EndOfFile);
+        let text = token.and_then(|token| {
+            if token.first() != Some(&b'"') {
+                return Ok(Unquoted::Borrowed(token));
+            }
+            unquote(token)
+        });
+        match text.map_err(|e| self.error(e, Expected::String))? {
+            Unquoted::Borrowed(s) => {
+                visitor.visit_borrowed_str(utf8(s).map_err(|e| self.error(e, Expected::String))?)
+            }
+            Unquoted::Unescaped(s) => {
+                visitor.visit_str(utf8(&s).map_err(|e| self.error(e, Expected::String))?)
+            }
+        }
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    // Unless quoted, bytes take the rest of the input.
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_borrowed_bytes(self.take_remaining().ok_or(FeroxError::EndOfFile)?)
+        self.skip_whitespace();
+        if self.input.get(self.index) != Some(&b'"') {
+            return visitor
+                .visit_borrowed_bytes(self.take_remaining().ok_or(FeroxError::EndOfFile)?);
+        }
+        let token = self.next_token().ok_or(FeroxError::EndOfFile)?;
+        match unquote(token).map_err(|e| self.error(e, Expected::String))? {
+            Unquoted::Borrowed(b) => visitor.visit_borrowed_bytes(b),
+            Unquoted::Unescaped(b) => visitor.visit_bytes(&b),
+        }
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        if let Some(token) = self.peek_token() {
-            if token == b"?" {
+        match self.peek_token() {
+            Some(b"?") => {
                 self.next_token(); // consume it
-                return visitor.visit_none();
+                visitor.visit_none()
             }
+            // A missing trailing argument is treated as `None`.
+            None => visitor.visit_none(),
+            Some(_) => visitor.visit_some(&mut *self),
         }
-        visitor.visit_some(&mut *self)
     }
 
-    fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_unit_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
-    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value>
+    // A sequence takes every remaining token, so it has to be the last argument.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_seq(TokensRef { de: self })
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_seq(FieldsRef {
+            de: self,
+            remaining: len,
+        })
     }
 
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
-        _visitor: V,
-    ) -> Result<V::Value>
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_seq(FieldsRef {
+            de: self,
+            remaining: len,
+        })
     }
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        match self.layout {
+            Layout::Positional => visitor.visit_map(KeyValueRef { de: self }),
+            Layout::Lines => visitor.visit_map(LinesRef {
+                de: self,
+                value: &[],
+            }),
+        }
     }
 
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
-        _visitor: V,
-    ) -> Result<V::Value>
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        match self.layout {
+            Layout::Positional => visitor.visit_seq(FieldsRef {
+                de: self,
+                remaining: fields.len(),
+            }),
+            Layout::Lines => visitor.visit_map(LinesRef {
+                de: self,
+                value: &[],
+            }),
+        }
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        Err(FeroxError::UnexpectedToken.into())
     }
 
     fn deserialize_enum<V>(
@@ -297,11 +715,19 @@ impl<'de> Deserializer<'de> for &mut AsciiDeserializer<'de> {
         _name: &'static str,
         _variants: &'static [&'static str],
         visitor: V,
-    ) -> Result<V::Value>
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        let variant_name = self.next_token().ok_or(FeroxError::EndOfFile)?;
+        let mut variant_name = self
+            .next_token()
+            .ok_or_else(|| self.error(FeroxError::EndOfFile, Expected::VariantName))?;
+        if let Some(commands) = self.commands.take() {
+            variant_name = resolve(commands, variant_name)
+                .map_err(|e| self.error(e, Expected::VariantName))?
+                .name
+                .as_bytes();
+        }
         debug!(
             "Deserializing enum variant: {:?}",
             core::str::from_utf8(variant_name).unwrap_or("<invalid utf8>")
@@ -312,11 +738,13 @@ impl<'de> Deserializer<'de> for &mut AsciiDeserializer<'de> {
         })
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    // Skips one token, e.g. the value of an unknown key.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        self.next_token();
+        visitor.visit_unit()
     }
 }
 
@@ -326,16 +754,23 @@ struct EnumRef<'a, 'de: 'a> {
 }
 
 impl<'de, 'a> EnumAccess<'de> for EnumRef<'a, 'de> {
-    type Error = FeroxError;
+    type Error = DeError;
     type Variant = VariantRef<'a, 'de>;
 
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), DeError>
     where
         V: DeserializeSeed<'de>,
     {
-        let s = core::str::from_utf8(self.variant_name).map_err(|_| FeroxError::Utf8Error)?;
-        debug!("Deserializing variant name: {:?}", s);
-        let v = seed.deserialize(s.into_deserializer())?;
+        let v = utf8(self.variant_name)
+            .map_err(DeError::from)
+            .and_then(|s| {
+                debug!("Deserializing variant name: {:?}", s);
+                seed.deserialize(s.into_deserializer())
+            })
+            .map_err(|e| DeError {
+                error: self.de.error(e.error, Expected::VariantName),
+                ..e
+            })?;
         let has_value = self.de.peek_token().is_some();
         Ok((
             v,
@@ -353,37 +788,170 @@ struct VariantRef<'a, 'de: 'a> {
 }
 
 impl<'de> VariantAccess<'de> for VariantRef<'_, 'de> {
-    type Error = FeroxError;
+    type Error = DeError;
 
-    fn unit_variant(self) -> Result<()> {
+    fn unit_variant(self) -> Result<(), DeError> {
         if self.has_value {
-            return Err(FeroxError::UnexpectedToken);
+            self.de.next_token();
+            return Err(self
+                .de
+                .error(FeroxError::UnexpectedToken, Expected::End)
+                .into());
         }
         Ok(())
     }
 
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, DeError>
     where
         T: DeserializeSeed<'de>,
     {
         if !self.has_value {
-            return Err(FeroxError::UnexpectedToken);
+            self.de.next_token();
+            return Err(self
+                .de
+                .error(FeroxError::UnexpectedToken, Expected::Argument)
+                .into());
         }
         seed.deserialize(&mut *self.de)
     }
 
-    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_seq(FieldsRef {
+            de: self.de,
+            remaining: len,
+        })
     }
 
-    fn struct_variant<V>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError>
     where
         V: Visitor<'de>,
     {
-        Err(FeroxError::UnexpectedToken)
+        visitor.visit_seq(FieldsRef {
+            de: self.de,
+            remaining: fields.len(),
+        })
+    }
+}
+
+/// Positional access to a fixed number of space-separated fields.
+struct FieldsRef<'a, 'de: 'a> {
+    de: &'a mut AsciiDeserializer<'de>,
+    remaining: usize,
+}
+
+impl<'de> SeqAccess<'de> for FieldsRef<'_, 'de> {
+    type Error = DeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, DeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+/// Access to space-separated elements until the end of input.
+struct TokensRef<'a, 'de: 'a> {
+    de: &'a mut AsciiDeserializer<'de>,
+}
+
+impl<'de> SeqAccess<'de> for TokensRef<'_, 'de> {
+    type Error = DeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, DeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.de.peek_token().is_none() {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+/// Access to `key: value` or `key value` lines until the end of input.
+///
+/// Each value is decoded from its own line only; anything after it on the line, such as a
+/// unit, is ignored.
+struct LinesRef<'a, 'de: 'a> {
+    de: &'a mut AsciiDeserializer<'de>,
+    value: &'de [u8],
+}
+
+impl<'de> MapAccess<'de> for LinesRef<'_, 'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, DeError>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.de.next_line() {
+            Some((key, value)) => {
+                self.value = value;
+                seed.deserialize(BorrowedStrDeserializer::new(utf8(key)?))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, DeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let mut line = AsciiDeserializer::new(self.value);
+        seed.deserialize(&mut line).inspect_err(|_| {
+            // Locate the error in the whole input rather than in the line.
+            if self.de.context.is_none() {
+                let mut context = line.error_context();
+                context.offset += self.value.as_ptr() as usize - self.de.input.as_ptr() as usize;
+                self.de.context = Some(context);
+            }
+        })
+    }
+}
+
+/// Access to space-separated `key=value` pairs until the end of input.
+struct KeyValueRef<'a, 'de: 'a> {
+    de: &'a mut AsciiDeserializer<'de>,
+}
+
+impl<'de> MapAccess<'de> for KeyValueRef<'_, 'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, DeError>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.de.next_key()? {
+            Some(key) => {
+                let s = core::str::from_utf8(key).map_err(|_| FeroxError::Utf8Error)?;
+                seed.deserialize(BorrowedStrDeserializer::new(s)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, DeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de)
     }
 }
 
@@ -393,7 +961,10 @@ mod tests {
 
     use super::*;
     use crate::{
-        proto::{ascii::from_bytes, error::Error},
+        proto::{
+            ascii::{from_bytes, from_bytes_strict, from_bytes_with_context, from_lines, to_bytes},
+            error::Error,
+        },
         testing::helpers::init_logger,
     };
 
@@ -413,6 +984,47 @@ mod tests {
 
         #[serde(rename = "varbytes2")]
         VarBytes2,
+
+        #[serde(rename = "setlimits")]
+        SetLimits { rtmin: f32, rtmax: Option<f32> },
+
+        #[serde(rename = "limits")]
+        Limits(Limits),
+
+        #[serde(rename = "ramp")]
+        Ramp(f32, f32, Option<i32>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum StrReq<'a> {
+        #[serde(rename = "name")]
+        Name(&'a str, i32),
+
+        #[serde(rename = "label")]
+        Label(heapless::String<32>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum ListReq {
+        #[serde(rename = "readings")]
+        Readings(heapless::Vec<f32, 4>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Numbers {
+        a: i8,
+        b: i16,
+        c: i64,
+        d: u16,
+        e: u32,
+        f: u64,
+        g: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Limits {
+        rtmin: f32,
+        rtmax: f32,
     }
 
     #[test]
@@ -446,6 +1058,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_deserialize_varfloat_some() {
         init_logger();
         let deserialized: TestReq = from_bytes(b"varfloat 3.14").unwrap();
@@ -503,4 +1116,451 @@ mod tests {
         assert_eq!(deserializer.next_token(), Some(&b"?"[..])); // Special character `?`
         assert_eq!(deserializer.next_token(), None); // End of input
     }
+
+    #[test]
+    fn test_deserialize_struct() {
+        init_logger();
+        let deserialized: Limits = from_bytes(b"1.5 2.5").unwrap();
+        assert_eq!(
+            deserialized,
+            Limits {
+                rtmin: 1.5,
+                rtmax: 2.5
+            }
+        );
+        assert_eq!(Error::EndOfFile, from_bytes::<Limits>(b"1.5").unwrap_err());
+    }
+
+    #[test]
+    fn test_deserialize_struct_variant() {
+        init_logger();
+        let deserialized: TestReq = from_bytes(b"setlimits 1.5 2.5").unwrap();
+        assert_eq!(
+            deserialized,
+            TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: Some(2.5)
+            }
+        );
+        let deserialized: TestReq = from_bytes(b"setlimits 1.5?").unwrap();
+        assert_eq!(
+            deserialized,
+            TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_map() {
+        init_logger();
+        let deserialized: heapless::FnvIndexMap<&str, i32, 4> =
+            from_bytes(b"lason=1 brate=9600").unwrap();
+        assert_eq!(deserialized.get("lason"), Some(&1));
+        assert_eq!(deserialized.get("brate"), Some(&9600));
+        assert_eq!(
+            Error::UnexpectedToken,
+            from_bytes::<heapless::FnvIndexMap<&str, i32, 4>>(b"lason 1").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_struct_round_trip() {
+        init_logger();
+        let requests = [
+            TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: Some(-2.25),
+            },
+            TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: None,
+            },
+            TestReq::Limits(Limits {
+                rtmin: 0.5,
+                rtmax: 10.0,
+            }),
+            TestReq::Ramp(10.0, 50.0, Some(200)),
+            TestReq::Ramp(10.0, 50.0, None),
+        ];
+        for req in requests {
+            let bytes = to_bytes(&req).unwrap();
+            assert_eq!(from_bytes::<TestReq>(&bytes).unwrap(), req);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_tuple_variant() {
+        init_logger();
+        let deserialized: TestReq = from_bytes(b"ramp 10.0 50.0 200").unwrap();
+        assert_eq!(deserialized, TestReq::Ramp(10.0, 50.0, Some(200)));
+
+        let deserialized: TestReq = from_bytes(b"ramp 10.0 50.0?").unwrap();
+        assert_eq!(deserialized, TestReq::Ramp(10.0, 50.0, None));
+
+        // Missing trailing optional arguments decode as `None`.
+        let deserialized: TestReq = from_bytes(b"ramp 10.0 50.0").unwrap();
+        assert_eq!(deserialized, TestReq::Ramp(10.0, 50.0, None));
+
+        assert_eq!(
+            Error::EndOfFile,
+            from_bytes::<TestReq>(b"ramp 10.0").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_numbers() {
+        init_logger();
+        let numbers = Numbers {
+            a: i8::MIN,
+            b: i16::MAX,
+            c: i64::MIN,
+            d: u16::MAX,
+            e: 115200,
+            f: u64::MAX,
+            g: -1.2345678e-10,
+        };
+        let deserialized: Numbers = from_bytes(
+            b"-128 32767 -9223372036854775808 65535 115200 18446744073709551615 -1.2345678e-10",
+        )
+        .unwrap();
+        assert_eq!(deserialized, numbers);
+        assert_eq!(
+            from_bytes::<Numbers>(&to_bytes(&numbers).unwrap()).unwrap(),
+            numbers
+        );
+    }
+
+    #[test]
+    fn test_deserialize_integer_overflow() {
+        init_logger();
+        assert_eq!(
+            Error::IntegerOverflow,
+            from_bytes::<i8>(b"128").unwrap_err()
+        );
+        assert_eq!(
+            Error::IntegerOverflow,
+            from_bytes::<u16>(b"-1").unwrap_err()
+        );
+        assert_eq!(
+            Error::IntegerOverflow,
+            from_bytes::<u64>(b"18446744073709551616").unwrap_err()
+        );
+        assert_eq!(
+            Error::IntegerOverflow,
+            from_bytes::<i64>(b"999999999999999999999999999999999999999999").unwrap_err()
+        );
+        assert_eq!(Error::ParseIntError, from_bytes::<u32>(b"12a").unwrap_err());
+        assert_eq!(
+            Error::ParseFloatError,
+            from_bytes::<f64>(b"1.2.3").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_seq() {
+        init_logger();
+        let readings: heapless::Vec<f32, 4> = from_bytes(b"1.2 3.4 5.6").unwrap();
+        assert_eq!(readings, [1.2, 3.4, 5.6]);
+
+        let empty: heapless::Vec<f32, 4> = from_bytes(b"").unwrap();
+        assert!(empty.is_empty());
+
+        let req: ListReq = from_bytes(b"readings 1.2 3.4").unwrap();
+        assert_eq!(
+            req,
+            ListReq::Readings(heapless::Vec::from_slice(&[1.2, 3.4]).unwrap())
+        );
+
+        assert_eq!(
+            Error::InvalidLength,
+            from_bytes::<heapless::Vec<f32, 2>>(b"1.2 3.4 5.6").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_tuple() {
+        init_logger();
+        let readings: [f32; 3] = from_bytes(b"1.2 3.4 5.6").unwrap();
+        assert_eq!(readings, [1.2, 3.4, 5.6]);
+
+        let tuple: (i32, f32, bool) = from_bytes(b"1 2.5 1").unwrap();
+        assert_eq!(tuple, (1, 2.5, true));
+
+        assert_eq!(
+            Error::EndOfFile,
+            from_bytes::<[f32; 3]>(b"1.2 3.4").unwrap_err()
+        );
+        assert_eq!(
+            Error::TrailingCharacters,
+            from_bytes_strict::<[f32; 3]>(b"1.2 3.4 5.6 7.8").unwrap_err()
+        );
+        // `from_bytes` stops after the value, as device responses may carry more.
+        assert_eq!(
+            from_bytes::<[f32; 3]>(b"1.2 3.4 5.6 7.8").unwrap(),
+            [1.2, 3.4, 5.6]
+        );
+        assert_eq!(from_bytes::<f32>(b"12.5 mA").unwrap(), 12.5);
+    }
+
+    #[test]
+    fn test_deserialize_quoted_str() {
+        init_logger();
+        let input = b"name \"hello world\" 5";
+        let req: StrReq = from_bytes(input).unwrap();
+        assert_eq!(req, StrReq::Name("hello world", 5));
+        // No escapes, so the string is borrowed straight from the input.
+        let StrReq::Name(s, _) = req else {
+            unreachable!()
+        };
+        assert!(input.as_ptr_range().contains(&s.as_ptr()));
+
+        let req: StrReq = from_bytes(b"name plain 5").unwrap();
+        assert_eq!(req, StrReq::Name("plain", 5));
+
+        let req: StrReq = from_bytes(b"name \"\" 5").unwrap();
+        assert_eq!(req, StrReq::Name("", 5));
+    }
+
+    #[test]
+    fn test_deserialize_escaped_str() {
+        init_logger();
+        let req: StrReq = from_bytes(br#"label "say \"hi\"\\\r\n""#).unwrap();
+        assert_eq!(
+            req,
+            StrReq::Label(heapless::String::try_from("say \"hi\"\\\r\n").unwrap())
+        );
+
+        // Borrowed strings cannot hold unescaped contents.
+        assert_eq!(
+            Error::InvalidRequestForDeserialize,
+            from_bytes::<StrReq>(br#"name "a\"b" 5"#).unwrap_err()
+        );
+        assert_eq!(
+            Error::UnterminatedString,
+            from_bytes::<StrReq>(br#"label "abc\""#).unwrap_err()
+        );
+        assert_eq!(
+            Error::InvalidEscape,
+            from_bytes::<StrReq>(br#"label "a\tb""#).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_quoted_bytes() {
+        init_logger();
+        let deserialized: TestReq = from_bytes(b"varbytes \"hello world\"").unwrap();
+        assert_eq!(deserialized, TestReq::VarBytes(Some(b"hello world")));
+
+        let deserialized: TestReq = from_bytes(b"varbytes hello world").unwrap();
+        assert_eq!(deserialized, TestReq::VarBytes(Some(b"hello world")));
+    }
+
+    #[test]
+    fn test_quoted_round_trip() {
+        init_logger();
+        let requests = [
+            StrReq::Label(heapless::String::try_from("hello world").unwrap()),
+            StrReq::Label(heapless::String::try_from("a \"b\" \\ c\r\n").unwrap()),
+            StrReq::Label(heapless::String::new()),
+            StrReq::Label(heapless::String::try_from("plain").unwrap()),
+        ];
+        for req in requests {
+            let bytes = to_bytes(&req).unwrap();
+            assert_eq!(from_bytes::<StrReq>(&bytes).unwrap(), req);
+        }
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Status<'a> {
+        lason: bool,
+        ilaser: f32,
+        #[serde(rename = "Tboard")]
+        tboard: Option<f32>,
+        serial: &'a str,
+        userdata: Option<&'a str>,
+    }
+
+    #[test]
+    fn test_deserialize_lines() {
+        init_logger();
+        let input = b"status\r\n\
+            lason 1\r\n\
+            ilaser: 12.5\r\n\
+            \r\n\
+            Tboard: 31.25 C\r\n\
+            unknown 7\r\n\
+            serial  \"KH 001\"\r\n\
+            userdata note:with-colon\r\n\
+            >>";
+        let status: Status = from_lines(input).unwrap();
+        assert_eq!(
+            status,
+            Status {
+                lason: true,
+                ilaser: 12.5,
+                tboard: Some(31.25),
+                serial: "KH 001",
+                userdata: Some("note:with-colon"),
+            }
+        );
+
+        assert_eq!(
+            Error::InvalidRequestForDeserialize,
+            from_lines::<Status>(b"lason 1\nilaser 1.0").unwrap_err()
+        );
+        assert_eq!(
+            Error::ParseFloatError,
+            from_lines::<Status>(b"ilaser abc").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_lines_map() {
+        init_logger();
+        let map: heapless::FnvIndexMap<&str, f32, 4> =
+            from_lines(b"itec: 0.5\nvtec: 1.25\n").unwrap();
+        assert_eq!(map.get("itec"), Some(&0.5));
+        assert_eq!(map.get("vtec"), Some(&1.25));
+    }
+
+    fn context(input: &[u8]) -> DeserializeError {
+        from_bytes_with_context::<TestReq>(input).unwrap_err()
+    }
+
+    #[test]
+    fn test_error_context() {
+        init_logger();
+        let e = context(b"varint 4x2");
+        assert_eq!(e.error, Error::ParseIntError);
+        assert_eq!(e.context.offset, 7);
+        assert_eq!(e.context.token, "4x2");
+        assert_eq!(e.context.expected, Some(Expected::Number));
+
+        let e = context(b"varbool  yes");
+        assert_eq!(e.error, Error::InvalidBoolean);
+        assert_eq!(e.context.offset, 9);
+        assert_eq!(e.context.expected, Some(Expected::Bool));
+
+        let e = context(b"  nosuchcommand 1");
+        assert_eq!(e.error, Error::InvalidRequestForDeserialize);
+        assert_eq!(e.context.offset, 2);
+        assert_eq!(e.context.token, "nosuchcommand");
+        assert_eq!(e.context.expected, Some(Expected::VariantName));
+
+        let e = context(b"ramp 1.5 oops");
+        assert_eq!(e.context.offset, 9);
+        assert_eq!(e.context.expected, Some(Expected::Number));
+
+        let e = context(b"varbytes2 extra");
+        assert_eq!(e.error, Error::UnexpectedToken);
+        assert_eq!(e.context.offset, 10);
+        assert_eq!(e.context.token, "extra");
+        assert_eq!(e.context.expected, Some(Expected::End));
+
+        let e = context(b"varint 1 2");
+        assert_eq!(e.error, Error::TrailingCharacters);
+        assert_eq!(e.context.offset, 9);
+        assert_eq!(e.context.expected, Some(Expected::End));
+
+        let e = context(b"setlimits");
+        assert_eq!(e.error, Error::EndOfFile);
+        assert_eq!(e.context.offset, 9);
+        assert_eq!(e.context.token, "");
+        assert_eq!(e.context.expected, Some(Expected::Number));
+
+        let e = context(b"limits ");
+        assert_eq!(e.error, Error::UnexpectedToken);
+        assert_eq!(e.context.offset, 7);
+        assert_eq!(e.context.expected, Some(Expected::Argument));
+    }
+
+    #[test]
+    fn test_error_context_display() {
+        init_logger();
+        extern crate std;
+        use std::string::ToString;
+
+        assert_eq!(
+            context(b"varint 4x2").context.to_string(),
+            "at byte 7 near \"4x2\", expected number"
+        );
+        assert_eq!(
+            context(b"limits").context.to_string(),
+            "at byte 6 (end of input), expected argument"
+        );
+        // Long tokens are truncated.
+        assert_eq!(
+            context(b"abcdefghijklmnopqrstuvwxyz").context.token,
+            "abcdefghijklmnop"
+        );
+    }
+
+    #[test]
+    fn test_error_context_lines() {
+        init_logger();
+        let input = b"lason 1\nilaser: abc\n";
+        let mut de = AsciiDeserializer::new(input).with_layout(Layout::Lines);
+        assert_eq!(
+            Status::deserialize(&mut de).unwrap_err().error,
+            Error::ParseFloatError
+        );
+        let context = de.error_context();
+        assert_eq!(context.offset, 16);
+        assert_eq!(context.token, "abc");
+        assert_eq!(context.expected, Some(Expected::Number));
+    }
+
+    #[test]
+    fn test_error_message() {
+        init_logger();
+        extern crate std;
+        use std::string::ToString;
+
+        let mut de = AsciiDeserializer::new(b"lason 1\nilaser: 1.5\n").with_layout(Layout::Lines);
+        let e = Status::deserialize(&mut de).unwrap_err();
+        assert_eq!(e.error, Error::InvalidRequestForDeserialize);
+        assert_eq!(e.message, "missing field `serial`");
+
+        let e = context(b"nosuchcommand 1");
+        assert_eq!(
+            e.context.message,
+            "unknown variant `nosuchcommand`, expecte"
+        );
+        assert_eq!(
+            e.context.to_string(),
+            "at byte 0 near \"nosuchcommand\", expected variant name: \
+             unknown variant `nosuchcommand`, expecte"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_number_literals() {
+        init_logger();
+        assert_eq!(
+            from_bytes::<TestReq>(b"varint 0x1F").unwrap(),
+            TestReq::VarInt(Some(31))
+        );
+        assert_eq!(
+            from_bytes::<TestReq>(b"varint 0b1010").unwrap(),
+            TestReq::VarInt(Some(10))
+        );
+        assert_eq!(
+            from_bytes::<TestReq>(b"ramp 150m 2.5k 10k").unwrap(),
+            TestReq::Ramp(0.15, 2500.0, Some(10_000))
+        );
+        assert_eq!(
+            from_bytes::<TestReq>(b"varfloat 10u").unwrap(),
+            TestReq::VarFloat(Some(10e-6))
+        );
+
+        let e = from_bytes_with_context::<TestReq>(b"varint 150m").unwrap_err();
+        assert_eq!(e.error, Error::ParseIntError);
+        assert_eq!(e.context.token, "150m");
+        assert_eq!(
+            from_bytes::<TestReq>(b"varint 0x100000000").unwrap_err(),
+            Error::IntegerOverflow
+        );
+    }
 }
@@ -0,0 +1,210 @@
+//! How floats are written.
+//!
+//! Floats are written without an exponent, unless they are too large or too small to be written
+//! in [`SCRATCH_SIZE`] characters that way, e.g. `1e300`. By default they are written with as
+//! many decimals as needed to read back the same value, e.g. `0.1`. Some instruments want a fixed
+//! number of decimals instead, or accept only a few characters per value.
+
+use core::fmt::{self, Display, LowerExp, Write};
+
+use heapless::String;
+
+use crate::proto::{error::Error as FeroxError, Result};
+
+/// Longest float written without an exponent. Room for any `f32` and for `f64`s of a sensible
+/// magnitude.
+pub const SCRATCH_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Notation {
+    /// Trailing zeros are dropped, e.g. `12.5`.
+    #[default]
+    Shortest,
+    /// Exactly `precision` decimals are written, e.g. `12.500`.
+    Fixed,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FloatFormat {
+    pub notation: Notation,
+    /// Most decimals written, rounding the value. `None` writes as many as needed to read back
+    /// the same value.
+    pub precision: Option<u8>,
+    /// Longest number written. Decimals are rounded away until the number fits; if even the
+    /// integer part is longer, writing fails with [`FeroxError::NumberTooLong`].
+    pub max_len: Option<u8>,
+}
+
+impl FloatFormat {
+    /// As many decimals as needed, e.g. `12.5`.
+    pub const fn shortest() -> Self {
+        Self {
+            notation: Notation::Shortest,
+            precision: None,
+            max_len: None,
+        }
+    }
+
+    /// Exactly `decimals` decimals, e.g. `12.500` for 3.
+    pub const fn fixed(decimals: u8) -> Self {
+        Self {
+            notation: Notation::Fixed,
+            precision: Some(decimals),
+            max_len: None,
+        }
+    }
+
+    pub const fn with_max_len(mut self, max_len: u8) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Writes `v` in this format.
+    pub fn write<W: Write, T: Display + LowerExp>(&self, w: &mut W, v: T) -> Result<()> {
+        let mut s = self.format(&v, self.precision)?;
+        if let Some(max_len) = self.max_len.map(usize::from) {
+            let mut decimals = decimals(&s);
+            while s.len() > max_len && decimals > 0 {
+                decimals -= 1;
+                // `decimals` is at most the length of the scratch string.
+                s = self.format(&v, Some(decimals as u8))?;
+            }
+            if s.len() > max_len {
+                return Err(FeroxError::NumberTooLong);
+            }
+        }
+        w.write_str(&s).map_err(|_| FeroxError::BufferOverflow)
+    }
+
+    fn format<T: Display + LowerExp>(
+        &self,
+        v: &T,
+        precision: Option<u8>,
+    ) -> Result<String<SCRATCH_SIZE>> {
+        let mut s = String::new();
+        let plain = match precision {
+            Some(p) => write!(s, "{:.*}", usize::from(p), v),
+            None => write!(s, "{}", v),
+        };
+        if plain.is_err() {
+            s.clear();
+            match precision {
+                Some(p) => write!(s, "{:.*e}", usize::from(p), v),
+                None => write!(s, "{:e}", v),
+            }
+            .map_err(|_: fmt::Error| FeroxError::BufferOverflow)?;
+        }
+        if self.notation == Notation::Shortest {
+            trim_zeros(&mut s);
+        }
+        Ok(s)
+    }
+}
+
+// Number of decimals of the mantissa.
+fn decimals(s: &str) -> usize {
+    let mantissa = s.split('e').next().unwrap_or(s);
+    mantissa.find('.').map_or(0, |dot| mantissa.len() - dot - 1)
+}
+
+// Drops trailing zeros of the mantissa, and its dot if no decimals are left.
+fn trim_zeros(s: &mut String<SCRATCH_SIZE>) {
+    let exp = s.find('e').unwrap_or(s.len());
+    if !s[..exp].contains('.') {
+        return;
+    }
+    let trimmed = s[..exp].trim_end_matches('0').trim_end_matches('.').len();
+    if trimmed == exp {
+        return;
+    }
+    let mut out = String::new();
+    // Cannot fail, `out` gets fewer bytes than `s` has.
+    let _ = out.push_str(&s[..trimmed]);
+    let _ = out.push_str(&s[exp..]);
+    *s = out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    fn write(format: FloatFormat, v: f32) -> Result<String<SCRATCH_SIZE>> {
+        let mut s = String::new();
+        format.write(&mut s, v).map(|_| s)
+    }
+
+    #[test]
+    fn test_shortest() {
+        init_logger();
+        let format = FloatFormat::shortest();
+        assert_eq!(write(format, 12.5).unwrap(), "12.5");
+        assert_eq!(write(format, 0.1).unwrap(), "0.1");
+        assert_eq!(write(format, 100.0).unwrap(), "100");
+        assert_eq!(write(format, 1e-10).unwrap(), "0.0000000001");
+
+        let format = FloatFormat {
+            precision: Some(2),
+            ..format
+        };
+        assert_eq!(write(format, 1.23456).unwrap(), "1.23");
+        assert_eq!(write(format, 2.5).unwrap(), "2.5");
+    }
+
+    #[test]
+    fn test_fixed() {
+        init_logger();
+        assert_eq!(write(FloatFormat::fixed(3), 12.5).unwrap(), "12.500");
+        assert_eq!(write(FloatFormat::fixed(0), 12.5).unwrap(), "12");
+        assert_eq!(write(FloatFormat::fixed(1), -0.06).unwrap(), "-0.1");
+    }
+
+    #[test]
+    fn test_extremes() {
+        init_logger();
+        let write64 = |format: FloatFormat, v: f64| {
+            let mut s = String::<SCRATCH_SIZE>::new();
+            format.write(&mut s, v).map(|_| s)
+        };
+        let format = FloatFormat::shortest();
+        assert_eq!(write64(format, 1e300).unwrap(), "1e300");
+        assert_eq!(write64(format, -1.5e300).unwrap(), "-1.5e300");
+        assert_eq!(
+            write64(format, f64::MIN_POSITIVE).unwrap(),
+            "2.2250738585072014e-308"
+        );
+        assert_eq!(
+            write64(format, f64::MAX).unwrap().parse::<f64>().unwrap(),
+            f64::MAX
+        );
+        assert_eq!(write64(FloatFormat::fixed(2), 1e300).unwrap(), "1.00e300");
+        assert_eq!(
+            write64(FloatFormat::fixed(2).with_max_len(5), 1e300).unwrap(),
+            "1e300"
+        );
+        // Fixed decimals of a tiny value do fit without an exponent.
+        assert_eq!(
+            write64(FloatFormat::fixed(3), f64::MIN_POSITIVE).unwrap(),
+            "0.000"
+        );
+    }
+
+    #[test]
+    fn test_max_len() {
+        init_logger();
+        let format = FloatFormat::shortest().with_max_len(6);
+        assert_eq!(write(format, 1.0 / 3.0).unwrap(), "0.3333");
+        assert_eq!(write(format, 12.5).unwrap(), "12.5");
+        assert_eq!(write(format, 99999.9).unwrap(), "100000");
+        assert_eq!(
+            write(format, 1234567.0).unwrap_err(),
+            FeroxError::NumberTooLong
+        );
+
+        let format = FloatFormat::fixed(3).with_max_len(6);
+        assert_eq!(write(format, 10000.75).unwrap(), "10001");
+        assert_eq!(write(format, 12.25).unwrap(), "12.250");
+    }
+}
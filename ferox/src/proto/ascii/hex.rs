@@ -0,0 +1,44 @@
+//! Integers written in hex, for bitmask registers such as the CTL200 `err`.
+//!
+//! Wrap a field in [`Hex`], or mark it `#[serde(with = "ferox::proto::ascii::hex")]`, and the
+//! ASCII serializer writes it as `0x1F` instead of `31`. Other formats see the plain integer.
+//! Decoding accepts any integer literal, see [`number`](super::number).
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// The newtype name the ASCII serializer recognizes.
+pub(crate) const NAME: &str = "$ferox::Hex";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Hex<T>(pub T);
+
+impl<T: Serialize> Serialize for Hex<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(NAME, &self.0)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Hex<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Hex)
+    }
+}
+
+/// For `#[serde(with = "...")]`; also works on `Option`s and sequences of integers.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    Hex(value).serialize(serializer)
+}
+
+/// For `#[serde(with = "...")]`.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer)
+}
@@ -0,0 +1,185 @@
+//! Numeric literals.
+//!
+//! Besides plain decimals, integers may be written in hex (`0x1F`) or binary (`0b1010`), and
+//! numbers may carry an SI suffix, e.g. `150m`, `2.5k` or `10u`. Integers only take suffixes
+//! that keep them whole, so `2k` is an integer but `150m` is not.
+
+use core::{num::IntErrorKind, str::FromStr};
+
+use crate::proto::{error::Error as FeroxError, Result};
+
+/// Floating point types numbers can be decoded into.
+pub trait Float: FromStr + Copy {
+    fn from_f64(v: f64) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f64(v: f64) -> Self {
+        v as f32
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(v: f64) -> Self {
+        v
+    }
+}
+
+/// Parses an integer literal.
+pub fn parse_int(s: &str) -> Result<i128> {
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let radix_digits = |prefixes: [&str; 2]| {
+        prefixes
+            .iter()
+            .find_map(|prefix| digits.strip_prefix(prefix))
+    };
+    let (radix, digits) = if let Some(hex) = radix_digits(["0x", "0X"]) {
+        (16, hex)
+    } else if let Some(bin) = radix_digits(["0b", "0B"]) {
+        (2, bin)
+    } else {
+        (10, digits)
+    };
+    // A second sign after the prefix is not a literal.
+    if digits.starts_with(['+', '-']) {
+        return Err(FeroxError::ParseIntError);
+    }
+
+    let magnitude = match u128::from_str_radix(digits, radix) {
+        Ok(v) => i128::try_from(v).map_err(|_| FeroxError::IntegerOverflow)?,
+        Err(e) if matches!(e.kind(), IntErrorKind::PosOverflow) => {
+            return Err(FeroxError::IntegerOverflow)
+        }
+        Err(_) if radix == 10 => parse_si_int(digits)?,
+        Err(_) => return Err(FeroxError::ParseIntError),
+    };
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+/// Parses a floating point literal.
+pub fn parse_float<T: Float>(s: &str) -> Result<T> {
+    if let Ok(v) = s.parse::<T>() {
+        return Ok(v);
+    }
+    if let Ok(v) = parse_int(s) {
+        return Ok(T::from_f64(v as f64));
+    }
+    let (mantissa, exponent) = split_si(s).ok_or(FeroxError::ParseFloatError)?;
+    let mantissa = mantissa
+        .parse::<f64>()
+        .map_err(|_| FeroxError::ParseFloatError)?;
+    if !mantissa.is_finite() {
+        return Err(FeroxError::ParseFloatError);
+    }
+    // One correctly rounded operation with an exact power of ten.
+    let scale = 10u64.pow(exponent.unsigned_abs()) as f64;
+    Ok(T::from_f64(if exponent < 0 {
+        mantissa / scale
+    } else {
+        mantissa * scale
+    }))
+}
+
+// Whole numbers such as `2k` or `1.5M`; the sign has already been stripped.
+fn parse_si_int(s: &str) -> Result<i128> {
+    let (mantissa, exponent) = split_si(s).ok_or(FeroxError::ParseIntError)?;
+    let exponent = u32::try_from(exponent).map_err(|_| FeroxError::ParseIntError)?;
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let fraction = fraction.trim_end_matches('0');
+    let fraction_len = u32::try_from(fraction.len()).map_err(|_| FeroxError::ParseIntError)?;
+    if fraction_len > exponent
+        || whole.is_empty() && fraction.is_empty()
+        || !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return Err(FeroxError::ParseIntError);
+    }
+
+    let mut v: i128 = 0;
+    for b in whole.bytes().chain(fraction.bytes()) {
+        v = v
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(i128::from(b - b'0')))
+            .ok_or(FeroxError::IntegerOverflow)?;
+    }
+    10i128
+        .checked_pow(exponent - fraction_len)
+        .and_then(|scale| v.checked_mul(scale))
+        .ok_or(FeroxError::IntegerOverflow)
+}
+
+// Splits `s` into the number and the power of ten of its SI suffix.
+fn split_si(s: &str) -> Option<(&str, i32)> {
+    let suffix = s.chars().next_back()?;
+    let exponent = match suffix {
+        'p' => -12,
+        'n' => -9,
+        'u' | 'µ' => -6,
+        'm' => -3,
+        'k' => 3,
+        'M' => 6,
+        'G' => 9,
+        _ => return None,
+    };
+    Some((&s[..s.len() - suffix.len_utf8()], exponent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    #[test]
+    fn test_parse_int() {
+        init_logger();
+        assert_eq!(parse_int("42"), Ok(42));
+        assert_eq!(parse_int("-42"), Ok(-42));
+        assert_eq!(parse_int("+7"), Ok(7));
+        assert_eq!(parse_int("0x1F"), Ok(31));
+        assert_eq!(parse_int("0XfF"), Ok(255));
+        assert_eq!(parse_int("-0x10"), Ok(-16));
+        assert_eq!(parse_int("0b1010"), Ok(10));
+        assert_eq!(parse_int("2k"), Ok(2_000));
+        assert_eq!(parse_int("2.5k"), Ok(2_500));
+        assert_eq!(parse_int("-1.25M"), Ok(-1_250_000));
+        assert_eq!(parse_int("3G"), Ok(3_000_000_000));
+
+        assert_eq!(parse_int("150m"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("2.5"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("1.2345k"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("0x"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("0b102"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("0x-1"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("k"), Err(FeroxError::ParseIntError));
+        assert_eq!(parse_int("abc"), Err(FeroxError::ParseIntError));
+        assert_eq!(
+            parse_int("0x100000000000000000000000000000000"),
+            Err(FeroxError::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn test_parse_float() {
+        init_logger();
+        assert_eq!(parse_float::<f32>("2.5"), Ok(2.5));
+        assert_eq!(parse_float::<f32>("-1e3"), Ok(-1000.0));
+        assert_eq!(parse_float::<f64>("150m"), Ok(0.15));
+        assert_eq!(parse_float::<f64>("2.5k"), Ok(2500.0));
+        assert_eq!(parse_float::<f64>("10u"), Ok(10e-6));
+        assert_eq!(parse_float::<f64>("10µ"), Ok(10e-6));
+        assert_eq!(parse_float::<f64>("-3n"), Ok(-3e-9));
+        assert_eq!(parse_float::<f32>("0x1F"), Ok(31.0));
+        assert_eq!(parse_float::<f32>("0b11"), Ok(3.0));
+        assert!(parse_float::<f32>("nan").unwrap().is_nan());
+
+        assert_eq!(parse_float::<f32>("m"), Err(FeroxError::ParseFloatError));
+        assert_eq!(parse_float::<f32>("1.5x"), Err(FeroxError::ParseFloatError));
+        assert_eq!(parse_float::<f32>("infk"), Err(FeroxError::ParseFloatError));
+    }
+}
@@ -0,0 +1,93 @@
+//! Quoted string tokens.
+//!
+//! A string argument that contains whitespace (or is empty) is sent as a quoted token, e.g.
+//! `userdata write "hello world"`. Inside quotes, `\"`, `\\`, `\r` and `\n` are escapes.
+
+use core::fmt::{self, Write};
+
+use heapless::Vec;
+
+use crate::{
+    proto::{error::Error as FeroxError, Result},
+    MAX_STRING_SIZE,
+};
+
+/// The contents of a quoted token.
+// Only ever lives on the stack while a single token is being decoded.
+#[allow(clippy::large_enum_variant)]
+pub enum Unquoted<'de> {
+    /// No escapes, so the contents are borrowed from the input.
+    Borrowed(&'de [u8]),
+    /// The contents had escapes and were copied out.
+    Unescaped(Vec<u8, MAX_STRING_SIZE>),
+}
+
+/// Returns true if `s` has to be quoted to survive as a single token.
+pub fn needs_quoting(s: &str) -> bool {
+    s.is_empty()
+        || s.bytes()
+            .any(|b| matches!(b, b' ' | b'\t' | b'\r' | b'\n' | b'"' | b'\\' | b'?'))
+}
+
+/// Writes `s` as a quoted token, escaping where necessary.
+pub fn write_quoted<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    let mut start = 0;
+    for (i, b) in s.bytes().enumerate() {
+        let escaped = match b {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\r' => "\\r",
+            b'\n' => "\\n",
+            _ => continue,
+        };
+        w.write_str(&s[start..i])?;
+        w.write_str(escaped)?;
+        start = i + 1;
+    }
+    w.write_str(&s[start..])?;
+    w.write_char('"')
+}
+
+/// Returns the length of the quoted token at the start of `input`, including both quotes, or
+/// `None` if the closing quote is missing.
+pub(crate) fn quoted_len(input: &[u8]) -> Option<usize> {
+    let mut i = 1;
+    while i < input.len() {
+        match input[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Strips the quotes from `token` and resolves its escapes.
+pub(crate) fn unquote(token: &[u8]) -> Result<Unquoted<'_>> {
+    if token.first() != Some(&b'"') || quoted_len(token) != Some(token.len()) {
+        return Err(FeroxError::UnterminatedString);
+    }
+    let body = &token[1..token.len() - 1];
+    if !body.contains(&b'\\') {
+        return Ok(Unquoted::Borrowed(body));
+    }
+
+    let mut out = Vec::new();
+    let mut bytes = body.iter();
+    while let Some(&b) = bytes.next() {
+        let c = if b == b'\\' {
+            match bytes.next() {
+                Some(b'"') => b'"',
+                Some(b'\\') => b'\\',
+                Some(b'r') => b'\r',
+                Some(b'n') => b'\n',
+                _ => return Err(FeroxError::InvalidEscape),
+            }
+        } else {
+            b
+        };
+        out.push(c).map_err(|_| FeroxError::BufferOverflow)?;
+    }
+    Ok(Unquoted::Unescaped(out))
+}
@@ -1,4 +1,4 @@
-use core::fmt::Write;
+use core::fmt::{self, Display, LowerExp, Write};
 
 use defmt_or_log::info;
 use postcard::ser_flavors::Flavor;
@@ -10,34 +10,145 @@ use serde::{
     Serialize, Serializer,
 };
 
+use super::{
+    float::FloatFormat,
+    hex,
+    quote::{needs_quoting, write_quoted},
+};
 use crate::proto::error::Error as FeroxError;
 
+/// Serializes values into the space-separated ASCII command format.
+///
+/// Wire shape:
+/// - unit variants are written as their name, e.g. `version`;
+/// - newtype, tuple and struct variants are written as their name followed by
+///   their fields separated by spaces, e.g. `setlimits 1.5 2.5`;
+/// - structs, tuples and sequences are written positionally, separated by spaces;
+/// - sequences of `u8` (e.g. `&[u8]`) are written as raw bytes without separators, while any
+///   other `u8` is a decimal number like the other integers;
+/// - maps are written as space-separated `key=value` pairs;
+/// - strings are quoted and escaped when they would not survive as one token, e.g.
+///   `"hello world"`;
+/// - `None` is written as `?` directly after the previous token, e.g. `varint?`;
+/// - integers wrapped in [`Hex`](super::hex::Hex) are written in hex, e.g. `0x1F`;
+/// - floats are written without an exponent unless they are very large or small, as set by
+///   [`FloatFormat`], e.g. `0.0001` or `1e300`.
 pub struct AsciiSerializer<F: Flavor> {
     buffer: F,
+    // Whether anything has been written to the buffer yet.
+    written: bool,
+    // A space is owed before the next token.
+    pending_space: bool,
+    // The last write was a `u8`, which is emitted as a raw byte.
+    raw_byte: bool,
+    // The value being written is a sequence element, where a `u8` is a raw byte.
+    in_seq: bool,
+    // Integers are written in hex, inside a `Hex` wrapper.
+    hex: bool,
+    float: FloatFormat,
 }
 
 impl<F: Flavor> AsciiSerializer<F> {
     pub fn new(buffer: F) -> Self {
-        Self { buffer }
+        Self {
+            buffer,
+            written: false,
+            pending_space: false,
+            raw_byte: false,
+            in_seq: false,
+            hex: false,
+            float: FloatFormat::default(),
+        }
+    }
+
+    pub fn with_float_format(mut self, format: FloatFormat) -> Self {
+        self.float = format;
+        self
+    }
+
+    fn flush_space(&mut self) -> Result<(), FeroxError> {
+        if self.pending_space && self.written {
+            self.buffer
+                .try_push(b' ')
+                .map_err(|_| FeroxError::BufferOverflow)?;
+        }
+        self.pending_space = false;
+        Ok(())
     }
 
     fn try_extend(&mut self, data: &[u8]) -> Result<(), FeroxError> {
+        self.flush_space()?;
         self.buffer
             .try_extend(data)
-            .map_err(|_| FeroxError::BufferOverflow)
+            .map_err(|_| FeroxError::BufferOverflow)?;
+        self.written = true;
+        self.raw_byte = false;
+        Ok(())
     }
 
     fn try_push(&mut self, data: u8) -> Result<(), FeroxError> {
+        self.flush_space()?;
         self.buffer
             .try_push(data)
+            .map_err(|_| FeroxError::BufferOverflow)?;
+        self.written = true;
+        self.raw_byte = false;
+        Ok(())
+    }
+
+    // Requests a single space before the next token. Leading spaces are never written.
+    fn separate(&mut self) {
+        self.pending_space = true;
+    }
+
+    // Separates sequence elements, except between raw bytes so that `&[u8]` stays contiguous.
+    fn separate_element(&mut self) {
+        if !self.raw_byte {
+            self.separate();
+        }
+    }
+
+    // Writes a value nested in a compound; `u8`s are raw bytes only directly inside a sequence.
+    fn nested<T: ?Sized + Serialize>(&mut self, value: &T, in_seq: bool) -> Result<(), FeroxError> {
+        let outer = core::mem::replace(&mut self.in_seq, in_seq);
+        let result = value.serialize(&mut *self);
+        self.in_seq = outer;
+        result
+    }
+
+    // Formats `value` straight into the output buffer, so there is no scratch string to outgrow.
+    fn write_display<T: Display + ?Sized>(&mut self, value: &T) -> Result<(), FeroxError> {
+        write!(DisplayWriter(self), "{}", value).map_err(|_| FeroxError::BufferOverflow)
+    }
+
+    fn write_int<T: Into<i128> + Display>(&mut self, v: T) -> Result<(), FeroxError> {
+        if !self.hex {
+            return self.write_display(&v);
+        }
+        let v = v.into();
+        let sign = if v < 0 { "-" } else { "" };
+        write!(DisplayWriter(self), "{}0x{:X}", sign, v.unsigned_abs())
             .map_err(|_| FeroxError::BufferOverflow)
     }
 
+    fn write_float<T: Display + LowerExp>(&mut self, v: T) -> Result<(), FeroxError> {
+        let format = self.float;
+        format.write(&mut DisplayWriter(self), v)
+    }
+
     pub fn finalize(self) -> F {
         self.buffer
     }
 }
 
+struct DisplayWriter<'a, F: Flavor>(&'a mut AsciiSerializer<F>);
+
+impl<F: Flavor> Write for DisplayWriter<'_, F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.try_extend(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
 impl<F: Flavor> SerializeSeq for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
@@ -46,7 +157,8 @@ impl<F: Flavor> SerializeSeq for &mut AsciiSerializer<F> {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        self.separate_element();
+        self.nested(value, true)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -58,15 +170,16 @@ impl<F: Flavor> SerializeTuple for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_element<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate_element();
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
 
@@ -74,30 +187,33 @@ impl<F: Flavor> SerializeTupleStruct for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate();
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
+
 impl<F: Flavor> SerializeTupleVariant for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate();
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
 
@@ -105,22 +221,24 @@ impl<F: Flavor> SerializeMap for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate();
+        self.nested(key, false)?;
+        self.try_push(b'=')
     }
 
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
 
@@ -128,15 +246,16 @@ impl<F: Flavor> SerializeStruct for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate();
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
 
@@ -144,15 +263,16 @@ impl<F: Flavor> SerializeStructVariant for &mut AsciiSerializer<F> {
     type Ok = ();
     type Error = FeroxError;
 
-    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        Err(Self::Error::NotSupportedInSerializing)
+        self.separate();
+        self.nested(value, false)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(())
     }
 }
 
@@ -170,18 +290,29 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
 
     fn serialize_str(self, value: &str) -> Result<Self::Ok, Self::Error> {
         info!("Serializing string: {}", value);
+        if needs_quoting(value) {
+            self.flush_space()?;
+            return write_quoted(&mut DisplayWriter(self), value)
+                .map_err(|_| FeroxError::BufferOverflow);
+        }
         self.try_extend(value.as_bytes())
     }
 
     fn serialize_newtype_struct<T>(
         self,
-        _: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: ?Sized + Serialize,
     {
         info!("Serializing newtype struct");
+        if name == hex::NAME {
+            let outer = core::mem::replace(&mut self.hex, true);
+            let result = value.serialize(&mut *self);
+            self.hex = outer;
+            return result;
+        }
         value.serialize(self)
     }
 
@@ -196,7 +327,7 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
             _name, variant_index, variant
         );
 
-        self.serialize_str(variant)
+        self.try_extend(variant.as_bytes())
     }
 
     fn serialize_newtype_variant<T>(
@@ -210,7 +341,8 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
         T: ?Sized + Serialize,
     {
         info!("Serializing newtype variant: {}", variant);
-        self.serialize_str(variant)?;
+        self.try_extend(variant.as_bytes())?;
+        self.separate();
         value.serialize(self)
     }
 
@@ -222,48 +354,49 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
         }
     }
 
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        let mut s = heapless::String::<10>::new();
-        write!(s, "{}", v).map_err(|_| FeroxError::BufferOverflow)?;
-        self.try_extend(s.as_bytes())
+        self.write_int(v)
     }
 
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.serialize_char(v as char)
+        if self.hex || !self.in_seq {
+            return self.write_int(v);
+        }
+        self.try_push(v)?;
+        self.raw_byte = true;
+        Ok(())
     }
 
-    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
-    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
-    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        self.write_int(v)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        let mut s = heapless::String::<10>::new();
-        write!(s, "{}", v).map_err(|_| FeroxError::BufferOverflow)?;
-        self.try_extend(s.as_bytes())
+        self.write_float(v)
     }
 
-    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        self.write_float(v)
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
@@ -275,6 +408,8 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        // `?` is glued to the previous token, e.g. `varint?`.
+        self.pending_space = false;
         self.serialize_char('?')
     }
 
@@ -282,7 +417,6 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
     where
         T: ?Sized + Serialize,
     {
-        self.serialize_char(' ')?;
         value.serialize(self)
     }
 
@@ -294,13 +428,12 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
         Err(Self::Error::NotSupportedInSerializing)
     }
 
-    // do nothing
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
         Ok(self)
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(self)
     }
 
     fn serialize_tuple_struct(
@@ -308,21 +441,23 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(self)
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        info!("Serializing tuple variant: {}", variant);
+        self.try_extend(variant.as_bytes())?;
+        Ok(self)
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(self)
     }
 
     fn serialize_struct(
@@ -330,7 +465,7 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Err(Self::Error::NotSupportedInSerializing)
+        Ok(self)
     }
 
     fn serialize_struct_variant(
@@ -344,8 +479,8 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
             "Serializing struct variant: name = {}, variant_index = {}, variant = {}, len = {}",
             name, variant_index, variant, len
         );
-        // TODO(xguo): Implement this.
-        Err(FeroxError::PlaceHolder)
+        self.try_extend(variant.as_bytes())?;
+        Ok(self)
     }
 
     fn collect_str<T>(self, _value: &T) -> Result<Self::Ok, Self::Error>
@@ -360,7 +495,19 @@ impl<F: Flavor> Serializer for &mut AsciiSerializer<F> {
 mod tests {
     use serde::{Deserialize, Serialize};
 
-    use crate::{proto::ascii::to_bytes, testing::helpers::init_logger};
+    use crate::{
+        proto::{
+            ascii::{float::FloatFormat, from_bytes, hex::Hex, to_bytes, to_bytes_with_format},
+            error::Error as FeroxError,
+        },
+        testing::helpers::init_logger,
+    };
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Limits {
+        rtmin: f32,
+        rtmax: f32,
+    }
 
     #[derive(Serialize, Deserialize, Debug)]
     enum TestReq<'a> {
@@ -378,6 +525,38 @@ mod tests {
 
         #[serde(rename = "varbytes2")]
         VarBytes2,
+
+        #[serde(rename = "setlimits")]
+        SetLimits { rtmin: f32, rtmax: Option<f32> },
+
+        #[serde(rename = "limits")]
+        Limits(Limits),
+
+        #[serde(rename = "ramp")]
+        Ramp(f32, f32, Option<i32>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    enum StrReq<'a> {
+        #[serde(rename = "name")]
+        Name(&'a str, i32),
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    enum ListReq {
+        #[serde(rename = "readings")]
+        Readings(heapless::Vec<f32, 4>),
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Numbers {
+        a: i8,
+        b: i16,
+        c: i64,
+        d: u16,
+        e: u32,
+        f: u64,
+        g: f64,
     }
 
     #[test]
@@ -399,6 +578,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_serialize_varfloat_some() {
         init_logger();
         assert_eq!(
@@ -446,4 +626,199 @@ mod tests {
         init_logger();
         assert_eq!(to_bytes(&TestReq::VarBytes(None)).unwrap(), b"varbytes?");
     }
+
+    #[test]
+    fn test_serialize_struct() {
+        init_logger();
+        let limits = Limits {
+            rtmin: 1.5,
+            rtmax: 2.5,
+        };
+        assert_eq!(to_bytes(&limits).unwrap(), b"1.5 2.5");
+        assert_eq!(
+            to_bytes(&TestReq::Limits(limits)).unwrap(),
+            b"limits 1.5 2.5"
+        );
+    }
+
+    #[test]
+    fn test_serialize_struct_variant() {
+        init_logger();
+        assert_eq!(
+            to_bytes(&TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: Some(2.5)
+            })
+            .unwrap(),
+            b"setlimits 1.5 2.5"
+        );
+        assert_eq!(
+            to_bytes(&TestReq::SetLimits {
+                rtmin: 1.5,
+                rtmax: None
+            })
+            .unwrap(),
+            b"setlimits 1.5?"
+        );
+    }
+
+    #[test]
+    fn test_serialize_map() {
+        init_logger();
+        let mut map = heapless::FnvIndexMap::<&str, i32, 4>::new();
+        map.insert("lason", 1).unwrap();
+        map.insert("brate", 9600).unwrap();
+        assert_eq!(to_bytes(&map).unwrap(), b"lason=1 brate=9600");
+    }
+
+    #[test]
+    fn test_serialize_tuple_variant() {
+        init_logger();
+        assert_eq!(
+            to_bytes(&TestReq::Ramp(10.5, 50.0, Some(200))).unwrap(),
+            b"ramp 10.5 50 200"
+        );
+        assert_eq!(
+            to_bytes(&TestReq::Ramp(10.5, 50.0, None)).unwrap(),
+            b"ramp 10.5 50?"
+        );
+    }
+
+    #[test]
+    fn test_serialize_numbers() {
+        init_logger();
+        let numbers = Numbers {
+            a: i8::MIN,
+            b: i16::MAX,
+            c: i64::MIN,
+            d: u16::MAX,
+            e: 115200,
+            f: u64::MAX,
+            g: -0.125,
+        };
+        assert_eq!(
+            to_bytes(&numbers).unwrap(),
+            b"-128 32767 -9223372036854775808 65535 115200 18446744073709551615 -0.125"
+        );
+    }
+
+    #[test]
+    fn test_u8_round_trip() {
+        init_logger();
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum ChannelReq {
+            #[serde(rename = "gain")]
+            Gain(u8, u8),
+        }
+        let req = ChannelReq::Gain(7, 255);
+        let bytes = to_bytes(&req).unwrap();
+        assert_eq!(bytes, b"gain 7 255");
+        assert_eq!(from_bytes::<ChannelReq>(&bytes).unwrap(), req);
+        assert_eq!(to_bytes(&(1_u8, 2_u16)).unwrap(), b"1 2");
+        // Byte strings stay raw.
+        assert_eq!(to_bytes(&&b"ok"[..]).unwrap(), b"ok");
+    }
+
+    #[test]
+    fn test_float_extremes_round_trip() {
+        init_logger();
+        for v in [1e300, f64::MAX, f64::MIN_POSITIVE, -5e-324] {
+            let bytes = to_bytes(&v).unwrap();
+            assert_eq!(from_bytes::<f64>(&bytes).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_serialize_long_float() {
+        init_logger();
+        assert_eq!(
+            to_bytes(&TestReq::VarFloat(Some(-1.2345678e-10))).unwrap(),
+            b"varfloat -0.00000000012345679"
+        );
+    }
+
+    #[test]
+    fn test_serialize_float_format() {
+        init_logger();
+        let limits = TestReq::SetLimits {
+            rtmin: 12.5,
+            rtmax: Some(1.0 / 3.0),
+        };
+        assert_eq!(
+            to_bytes_with_format(&limits, FloatFormat::fixed(3)).unwrap(),
+            b"setlimits 12.500 0.333"
+        );
+        assert_eq!(
+            to_bytes_with_format(&limits, FloatFormat::shortest().with_max_len(5)).unwrap(),
+            b"setlimits 12.5 0.333"
+        );
+        assert_eq!(
+            to_bytes_with_format(
+                &TestReq::VarFloat(Some(-123456.0)),
+                FloatFormat::fixed(1).with_max_len(5)
+            )
+            .unwrap_err(),
+            FeroxError::NumberTooLong
+        );
+    }
+
+    #[test]
+    fn test_serialize_seq() {
+        init_logger();
+        let readings = heapless::Vec::<f32, 4>::from_slice(&[1.2, 3.4, 5.6]).unwrap();
+        assert_eq!(to_bytes(&readings).unwrap(), b"1.2 3.4 5.6");
+        assert_eq!(
+            to_bytes(&ListReq::Readings(readings)).unwrap(),
+            b"readings 1.2 3.4 5.6"
+        );
+        assert_eq!(to_bytes(&[1_i32, -2, 3]).unwrap(), b"1 -2 3");
+        assert_eq!(to_bytes(&(1_i32, 2.5_f32, true)).unwrap(), b"1 2.5 1");
+    }
+
+    #[test]
+    fn test_serialize_quoted_str() {
+        init_logger();
+        assert_eq!(
+            to_bytes(&StrReq::Name("plain", 5)).unwrap(),
+            b"name plain 5"
+        );
+        assert_eq!(
+            to_bytes(&StrReq::Name("hello world", 5)).unwrap(),
+            b"name \"hello world\" 5"
+        );
+        assert_eq!(to_bytes(&StrReq::Name("", 5)).unwrap(), b"name \"\" 5");
+        assert_eq!(
+            to_bytes(&StrReq::Name("say \"hi\"\\\r\n", 5)).unwrap(),
+            br#"name "say \"hi\"\\\r\n" 5"#
+        );
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Registers {
+        mask: Hex<u32>,
+        #[serde(with = "crate::proto::ascii::hex")]
+        flags: Option<i16>,
+        small: Hex<u8>,
+        count: u32,
+    }
+
+    #[test]
+    fn test_serialize_hex() {
+        init_logger();
+        let registers = Registers {
+            mask: Hex(0x1F),
+            flags: Some(-16),
+            small: Hex(10),
+            count: 31,
+        };
+        let bytes = to_bytes(&registers).unwrap();
+        assert_eq!(bytes, b"0x1F -0x10 0xA 31");
+        assert_eq!(from_bytes::<Registers>(&bytes).unwrap(), registers);
+
+        let registers = Registers {
+            flags: None,
+            ..registers
+        };
+        assert_eq!(to_bytes(&registers).unwrap(), b"0x1F? 0xA 31");
+    }
 }
@@ -0,0 +1,319 @@
+//! Decoding ASCII responses straight from an async reader.
+//!
+//! [`from_bytes`](super::from_bytes) needs the whole message in memory first. A
+//! [`StreamDeserializer`] instead pulls the response line by line through a window of `W` bytes,
+//! so responses far larger than [`MAX_STRING_SIZE`](crate::MAX_STRING_SIZE) can be decoded as
+//! long as every single line fits into the window.
+
+use defmt_or_log::debug;
+use embedded_io_async::Read;
+use heapless::Vec;
+use serde::{
+    de::{DeserializeOwned, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use super::{deser::split_key, from_bytes, from_lines};
+use crate::proto::{
+    error::{Error as FeroxError, ErrorReport},
+    Result,
+};
+
+pub struct StreamDeserializer<'t, R, const W: usize> {
+    reader: R,
+    terminator: &'t [u8],
+    window: [u8; W],
+    // Unread data is `window[start..end]`.
+    start: usize,
+    end: usize,
+    done: bool,
+}
+
+impl<'t, R: Read, const W: usize> StreamDeserializer<'t, R, W> {
+    /// Creates a deserializer that reads from `reader` until `terminator`, e.g. a prompt, or
+    /// until the reader reports end of input.
+    pub fn new(reader: R, terminator: &'t [u8]) -> Self {
+        Self {
+            reader,
+            terminator,
+            window: [0; W],
+            start: 0,
+            end: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the next line without its line ending, or `None` once the terminator was read.
+    pub async fn next_line(&mut self) -> Result<Option<&[u8]>, ErrorReport> {
+        Ok(self
+            .next_line_range()
+            .await?
+            .map(|(start, end)| &self.window[start..end]))
+    }
+
+    /// Decodes the next non-empty line positionally, like [`from_bytes`].
+    pub async fn next<'s, T>(&'s mut self) -> Result<Option<T>, ErrorReport>
+    where
+        T: Deserialize<'s>,
+    {
+        while let Some((start, end)) = self.next_line_range().await? {
+            if !self.window[start..end].trim_ascii().is_empty() {
+                return Ok(Some(from_bytes(&self.window[start..end])?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes `key: value` or `key value` lines up to the terminator into a struct, like
+    /// [`from_lines`].
+    ///
+    /// Only lines whose key is a field of `T` are kept, so the dump itself may be any size as
+    /// long as the wanted lines fit into `N` bytes. For maps every line is kept.
+    pub async fn decode_lines<T, const N: usize>(&mut self) -> Result<T, ErrorReport>
+    where
+        T: DeserializeOwned,
+    {
+        let fields = probe_fields::<T>();
+        let mut kept = Vec::<u8, N>::new();
+        while let Some(line) = self.next_line().await? {
+            let key = line_key(line);
+            if fields.is_some_and(|fields| !fields.iter().any(|f| f.as_bytes() == key)) {
+                debug!(
+                    "Skipping line: {:?}",
+                    core::str::from_utf8(line).unwrap_or("<invalid utf8>")
+                );
+                continue;
+            }
+            kept.extend_from_slice(line)
+                .map_err(|_| FeroxError::BufferOverflow)?;
+            kept.push(b'\n').map_err(|_| FeroxError::BufferOverflow)?;
+        }
+        Ok(from_lines(&kept)?)
+    }
+
+    async fn next_line_range(&mut self) -> Result<Option<(usize, usize)>, ErrorReport> {
+        loop {
+            if self.done {
+                return Ok(None);
+            }
+
+            let data = &self.window[self.start..self.end];
+            let newline = data.iter().position(|&b| b == b'\n');
+            match find(data, self.terminator) {
+                Some(t) if newline.is_none_or(|n| t <= n) => {
+                    let line = (self.start, self.start + trim_line_end(&data[..t]).len());
+                    self.start += t + self.terminator.len();
+                    self.done = true;
+                    return Ok(Some(line).filter(|(start, end)| start < end));
+                }
+                _ => {}
+            }
+
+            // Line breaks are left unread until it is clear they do not begin the terminator.
+            let breaks = data
+                .iter()
+                .take_while(|&&b| matches!(b, b'\r' | b'\n'))
+                .count();
+            if breaks > 0 && !self.terminator.starts_with(data) {
+                self.start += breaks;
+                continue;
+            }
+            if let Some(n) = newline.filter(|&n| n >= breaks) {
+                let line = (self.start, self.start + trim_line_end(&data[..n]).len());
+                self.start = line.1;
+                return Ok(Some(line));
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), ErrorReport> {
+        if self.start > 0 {
+            self.window.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == W {
+            return Err(FeroxError::BufferOverflow.into());
+        }
+        let n = self
+            .reader
+            .read(&mut self.window[self.end..])
+            .await
+            .map_err(|e| ErrorReport::from_io(FeroxError::ReadError, e))?;
+        if n == 0 {
+            // End of input acts as the terminator.
+            self.terminator = &[];
+        }
+        self.end += n;
+        Ok(())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(haystack.len());
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn line_key(line: &[u8]) -> &[u8] {
+    split_key(line.trim_ascii()).0
+}
+
+// Finds the field names of a struct without decoding anything, or `None` if `T` is not a struct.
+fn probe_fields<T: DeserializeOwned>() -> Option<&'static [&'static str]> {
+    let mut fields = None;
+    let _ = T::deserialize(FieldsProbe {
+        fields: &mut fields,
+    });
+    fields
+}
+
+struct FieldsProbe<'a> {
+    fields: &'a mut Option<&'static [&'static str]>,
+}
+
+impl<'de> Deserializer<'de> for FieldsProbe<'_> {
+    type Error = FeroxError;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(FeroxError::UnexpectedToken)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        *self.fields = Some(fields);
+        Err(FeroxError::UnexpectedToken)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec as StdVec;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::testing::helpers::{init_logger, ChunkedReader};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Status {
+        lason: bool,
+        ilaser: f32,
+        tboard: Option<f32>,
+    }
+
+    #[tokio::test]
+    async fn test_next_line() {
+        init_logger();
+        for chunk in 1..8 {
+            let mut de = StreamDeserializer::<_, 16>::new(
+                ChunkedReader::every(b"a 1\r\nb 2\r\n\r\nc\r\n>>x", chunk),
+                b"\r\n>>",
+            );
+            assert_eq!(de.next_line().await.unwrap(), Some(&b"a 1"[..]));
+            assert_eq!(de.next_line().await.unwrap(), Some(&b"b 2"[..]));
+            assert_eq!(de.next_line().await.unwrap(), Some(&b"c"[..]));
+            assert_eq!(de.next_line().await.unwrap(), None);
+            assert_eq!(de.next_line().await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_next_line_without_terminator() {
+        init_logger();
+        let mut de =
+            StreamDeserializer::<_, 16>::new(ChunkedReader::every(b"a 1\nb 2", 3), b"\r\n>>");
+        assert_eq!(de.next_line().await.unwrap(), Some(&b"a 1"[..]));
+        assert_eq!(de.next_line().await.unwrap(), Some(&b"b 2"[..]));
+        assert_eq!(de.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_line_too_long() {
+        init_logger();
+        let mut de =
+            StreamDeserializer::<_, 4>::new(ChunkedReader::every(b"abcdef\r\n>>", 2), b"\r\n>>");
+        assert_eq!(
+            de.next_line().await.unwrap_err().error,
+            FeroxError::BufferOverflow
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_error_kind() {
+        init_logger();
+        struct Failing;
+        impl embedded_io_async::ErrorType for Failing {
+            type Error = embedded_io::ErrorKind;
+        }
+        impl Read for Failing {
+            async fn read(&mut self, _buf: &mut [u8]) -> core::result::Result<usize, Self::Error> {
+                Err(embedded_io::ErrorKind::TimedOut)
+            }
+        }
+        let mut de = StreamDeserializer::<_, 16>::new(Failing, b"\r\n>>");
+        let report = de.next_line().await.unwrap_err();
+        assert_eq!(report.error, FeroxError::ReadError);
+        assert_eq!(report.io, Some(embedded_io::ErrorKind::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn test_next() {
+        init_logger();
+        let mut de = StreamDeserializer::<_, 16>::new(
+            ChunkedReader::every(b"1.5 2\r\n\r\n3 4\r\n>>", 3),
+            b"\r\n>>",
+        );
+        assert_eq!(de.next::<(f32, i32)>().await.unwrap(), Some((1.5, 2)));
+        assert_eq!(de.next::<(f32, i32)>().await.unwrap(), Some((3.0, 4)));
+        assert_eq!(de.next::<(f32, i32)>().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_decode_lines_larger_than_buffer() {
+        init_logger();
+        let mut dump = StdVec::new();
+        dump.extend_from_slice(b"status\r\nlason 1\r\n");
+        for i in 0..100 {
+            dump.extend_from_slice(std::format!("unknown{} {}\r\n", i, i).as_bytes());
+        }
+        dump.extend_from_slice(b"ilaser: 12.5\r\n>>");
+        assert!(dump.len() > crate::MAX_STRING_SIZE);
+
+        let mut de =
+            StreamDeserializer::<_, 32>::new(ChunkedReader::every(dump.leak(), 7), b"\r\n>>");
+        let status: Status = de.decode_lines::<_, 64>().await.unwrap();
+        assert_eq!(
+            status,
+            Status {
+                lason: true,
+                ilaser: 12.5,
+                tboard: None,
+            }
+        );
+    }
+}
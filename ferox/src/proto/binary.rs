@@ -0,0 +1,134 @@
+//! Binary framing for automated controllers.
+//!
+//! A message is encoded with postcard, followed by a CRC-16 of the encoded bytes (little
+//! endian), COBS-encoded so that it contains no zero bytes, and terminated by [`FRAME_END`].
+//! Frames cannot be confused with ASCII lines, which never contain a zero byte.
+
+use crc::{Crc, CRC_16_IBM_3740};
+use postcard::{de_flavors::crc::from_bytes_u16, ser_flavors::crc::to_slice_u16};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    proto::{error::Error as FeroxError, Result},
+    MAX_STRING_SIZE,
+};
+
+/// Terminates every frame.
+pub const FRAME_END: u8 = 0x00;
+
+/// Size of the CRC appended to every message.
+pub const CRC_SIZE: usize = 2;
+
+/// Largest frame for a message of up to [`MAX_STRING_SIZE`] encoded bytes.
+pub const MAX_FRAME_SIZE: usize = max_frame_size(MAX_STRING_SIZE);
+
+static CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+/// Size of the frame for a message of `len` encoded bytes, in the worst case.
+pub const fn max_frame_size(len: usize) -> usize {
+    cobs::max_encoding_length(len + CRC_SIZE) + 1
+}
+
+/// Encodes `value` as a frame into `buf`, including [`FRAME_END`], and returns its length.
+pub fn to_frame<T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where
+    T: Serialize + ?Sized,
+{
+    let mut message = [0u8; MAX_STRING_SIZE + CRC_SIZE];
+    let message = to_slice_u16(value, &mut message, CRC.digest()).map_err(map_postcard)?;
+    let len = cobs::try_encode(message, buf).map_err(|_| FeroxError::BufferOverflow)?;
+    *buf.get_mut(len).ok_or(FeroxError::BufferOverflow)? = FRAME_END;
+    Ok(len + 1)
+}
+
+/// Decodes a frame, with or without its [`FRAME_END`]. The frame is decoded in place.
+pub fn from_frame<'de, T>(frame: &'de mut [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    let end = frame.len() - usize::from(frame.last() == Some(&FRAME_END));
+    let frame = &mut frame[..end];
+    let len = cobs::decode_in_place(frame).map_err(|_| FeroxError::InvalidFrame)?;
+    from_bytes_u16(&frame[..len], CRC.digest()).map_err(map_postcard)
+}
+
+fn map_postcard(e: postcard::Error) -> FeroxError {
+    match e {
+        postcard::Error::SerializeBufferFull => FeroxError::BufferOverflow,
+        postcard::Error::DeserializeBadCrc => FeroxError::CrcMismatch,
+        postcard::Error::DeserializeUnexpectedEnd => FeroxError::EndOfFile,
+        _ => FeroxError::InvalidFrame,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proto::ferox::{FeroxRequest, FeroxResponse, SmcRequest},
+        testing::helpers::init_logger,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        init_logger();
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let len = to_frame(&FeroxRequest::AllVersions, &mut buf).unwrap();
+        assert_eq!(buf[len - 1], FRAME_END);
+        assert!(!buf[..len - 1].contains(&FRAME_END));
+        assert_eq!(
+            from_frame::<FeroxRequest>(&mut buf[..len]).unwrap(),
+            FeroxRequest::AllVersions
+        );
+
+        let response = FeroxResponse::AllVersions {
+            ctl200: "V0.17",
+            smc: "1.2.3",
+        };
+        let len = to_frame(&response, &mut buf).unwrap();
+        assert_eq!(
+            from_frame::<FeroxResponse>(&mut buf[..len]).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn test_frame_without_end() {
+        init_logger();
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let request = SmcRequest::Version(Some(&[0, 1, 0]));
+        let len = to_frame(&request, &mut buf).unwrap();
+        assert!(!buf[..len - 1].contains(&FRAME_END));
+        assert_eq!(
+            from_frame::<SmcRequest>(&mut buf[..len - 1]).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn test_corrupted_frame() {
+        init_logger();
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let len = to_frame(&FeroxResponse::Error(0x1234), &mut buf).unwrap();
+        buf[2] ^= 0x40;
+        assert_eq!(
+            from_frame::<FeroxResponse>(&mut buf[..len]).unwrap_err(),
+            FeroxError::CrcMismatch
+        );
+
+        assert_eq!(
+            from_frame::<FeroxResponse>(&mut [5, 1, FRAME_END]).unwrap_err(),
+            FeroxError::InvalidFrame
+        );
+    }
+
+    #[test]
+    fn test_buffer_too_small() {
+        init_logger();
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            to_frame(&FeroxResponse::Text("help text"), &mut buf).unwrap_err(),
+            FeroxError::BufferOverflow
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Static descriptions of ASCII commands.
+//!
+//! Request enums marked with [`ascii_command`] carry a table of their commands. The server uses
+//! it to answer `help` and to reject malformed commands before dispatching them.
+
+use core::{
+    fmt::{self, Display},
+    iter,
+};
+
+pub use ferox_derive::ascii_command;
+
+use crate::proto::{ascii::deser::AsciiDeserializer, error::Error, Result};
+
+/// One argument of a command.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ArgInfo {
+    /// Field name for struct variants, `None` for tuple variants.
+    pub name: Option<&'static str>,
+    /// The Rust type as written in the request enum, e.g. `Option<f32>`.
+    pub ty: &'static str,
+    /// Whether the argument may be left out.
+    pub optional: bool,
+}
+
+/// One command of a request enum.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// Other names accepted when decoding, from `#[command(alias = "...")]`.
+    pub aliases: &'static [&'static str],
+    pub args: &'static [ArgInfo],
+    /// The value can be read, with `name?` or `name` alone.
+    pub get: bool,
+    /// The value can be written, with `name args...`.
+    pub set: bool,
+    /// The doc comment of the variant, joined into one line.
+    pub doc: &'static str,
+}
+
+impl CommandInfo {
+    /// Number of arguments that cannot be left out.
+    pub fn required_args(&self) -> usize {
+        self.args.iter().filter(|arg| !arg.optional).count()
+    }
+
+    /// The name followed by the aliases.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        iter::once(self.name).chain(self.aliases.iter().copied())
+    }
+}
+
+/// Finds the command called `name`, ignoring ASCII case and accepting aliases.
+///
+/// If no name matches exactly, `name` may also be a prefix of the names of exactly one command;
+/// a prefix of several commands is [`Error::AmbiguousCommand`].
+pub fn resolve(commands: &'static [CommandInfo], name: &[u8]) -> Result<&'static CommandInfo> {
+    if name.is_empty() {
+        return Err(Error::UnknownCommand);
+    }
+    if let Some(command) = commands
+        .iter()
+        .find(|c| c.names().any(|n| n.as_bytes().eq_ignore_ascii_case(name)))
+    {
+        return Ok(command);
+    }
+
+    let mut candidates = commands.iter().filter(|c| {
+        c.names().any(|n| {
+            n.as_bytes()
+                .get(..name.len())
+                .is_some_and(|p| p.eq_ignore_ascii_case(name))
+        })
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some(command), None) => Ok(command),
+        (Some(_), Some(_)) => Err(Error::AmbiguousCommand),
+        (None, _) => Err(Error::UnknownCommand),
+    }
+}
+
+/// Prints a usage line, e.g. `setlimits <rtmin: f32> [rtmax: f32] (set): Sets the limits.`
+///
+/// Aliases follow the name, separated by `|`.
+impl Display for CommandInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        for alias in self.aliases {
+            write!(f, "|{}", alias)?;
+        }
+        for arg in self.args {
+            let (open, close) = if arg.optional { ('[', ']') } else { ('<', '>') };
+            // Optional arguments are shown without their `Option`.
+            let ty = arg
+                .ty
+                .strip_prefix("Option<")
+                .and_then(|ty| ty.strip_suffix('>'))
+                .filter(|_| arg.optional)
+                .unwrap_or(arg.ty);
+            match arg.name {
+                Some(name) => write!(f, " {}{}: {}{}", open, name, ty, close)?,
+                None => write!(f, " {}{}{}", open, ty, close)?,
+            }
+        }
+        let access = match (self.get, self.set) {
+            (true, true) => "get/set",
+            (true, false) => "get",
+            (false, true) => "set",
+            (false, false) => "",
+        };
+        if !access.is_empty() {
+            write!(f, " ({})", access)?;
+        }
+        if !self.doc.is_empty() {
+            write!(f, ": {}", self.doc)?;
+        }
+        Ok(())
+    }
+}
+
+/// A request enum with a command table, implemented by [`ascii_command`].
+pub trait Commands {
+    const COMMANDS: &'static [CommandInfo];
+
+    /// Looks up a command by its name on the wire.
+    fn command(name: &str) -> Option<&'static CommandInfo> {
+        Self::COMMANDS.iter().find(|c| c.name == name)
+    }
+
+    /// Checks that `line` names a known command and only reads or writes it if it allows that.
+    /// The name is matched like [`resolve`] does.
+    ///
+    /// The arguments themselves are checked when the line is decoded.
+    fn validate(line: &[u8]) -> Result<&'static CommandInfo> {
+        let mut de = AsciiDeserializer::new(line);
+        let name = de.next_token().ok_or(Error::UnknownCommand)?;
+        let command = resolve(Self::COMMANDS, name)?;
+        match de.next_token() {
+            Some(b"?") if !command.get => Err(Error::NotReadable),
+            Some(b"?") => Ok(command),
+            Some(_) if !command.set => Err(Error::NotWritable),
+            None if !command.get && command.required_args() > 0 => Err(Error::NotReadable),
+            _ => Ok(command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        proto::ascii::{deser::Expected, from_bytes, from_bytes_relaxed, to_bytes},
+        testing::helpers::init_logger,
+    };
+
+    #[ascii_command]
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum TestReq {
+        /// Firmware version.
+        Version,
+
+        /// Laser current
+        /// in mA.
+        #[command(name = "ilaser")]
+        LaserCurrent(Option<f32>),
+
+        #[serde(rename = "setlimits")]
+        #[command(alias = "limits", alias = "lim")]
+        SetLimits { rtmin: f32, rtmax: Option<f32> },
+
+        /// Writes the configuration to flash.
+        #[command(name = "save", set)]
+        Save,
+    }
+
+    #[test]
+    fn test_command_table() {
+        init_logger();
+        assert_eq!(
+            TestReq::COMMANDS,
+            &[
+                CommandInfo {
+                    name: "version",
+                    aliases: &[],
+                    args: &[],
+                    get: true,
+                    set: false,
+                    doc: "Firmware version.",
+                },
+                CommandInfo {
+                    name: "ilaser",
+                    aliases: &[],
+                    args: &[ArgInfo {
+                        name: None,
+                        ty: "Option<f32>",
+                        optional: true,
+                    }],
+                    get: true,
+                    set: true,
+                    doc: "Laser current in mA.",
+                },
+                CommandInfo {
+                    name: "setlimits",
+                    aliases: &["limits", "lim"],
+                    args: &[
+                        ArgInfo {
+                            name: Some("rtmin"),
+                            ty: "f32",
+                            optional: false,
+                        },
+                        ArgInfo {
+                            name: Some("rtmax"),
+                            ty: "Option<f32>",
+                            optional: true,
+                        },
+                    ],
+                    get: false,
+                    set: true,
+                    doc: "",
+                },
+                CommandInfo {
+                    name: "save",
+                    aliases: &[],
+                    args: &[],
+                    get: false,
+                    set: true,
+                    doc: "Writes the configuration to flash.",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ascii_mapping() {
+        init_logger();
+        assert_eq!(to_bytes(&TestReq::Version).unwrap(), b"version");
+        assert_eq!(to_bytes(&TestReq::LaserCurrent(None)).unwrap(), b"ilaser?");
+        assert_eq!(
+            from_bytes::<TestReq>(b"ilaser 1.5").unwrap(),
+            TestReq::LaserCurrent(Some(1.5))
+        );
+        assert_eq!(from_bytes::<TestReq>(b"save").unwrap(), TestReq::Save);
+    }
+
+    #[test]
+    fn test_usage_line() {
+        init_logger();
+        let usage = |name| TestReq::command(name).unwrap().to_string();
+        assert_eq!(usage("version"), "version (get): Firmware version.");
+        assert_eq!(
+            usage("ilaser"),
+            "ilaser [f32] (get/set): Laser current in mA."
+        );
+        assert_eq!(
+            usage("setlimits"),
+            "setlimits|limits|lim <rtmin: f32> [rtmax: f32] (set)"
+        );
+        assert!(TestReq::command("nosuch").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        init_logger();
+        assert_eq!(TestReq::validate(b"version").unwrap().name, "version");
+        assert_eq!(TestReq::validate(b"ilaser?").unwrap().name, "ilaser");
+        assert_eq!(TestReq::validate(b"ilaser 1.5").unwrap().name, "ilaser");
+        assert!(TestReq::validate(b"setlimits 1 2").is_ok());
+        assert!(TestReq::validate(b"save").is_ok());
+
+        assert_eq!(TestReq::validate(b"").unwrap_err(), Error::UnknownCommand);
+        assert_eq!(
+            TestReq::validate(b"nosuch 1").unwrap_err(),
+            Error::UnknownCommand
+        );
+        assert_eq!(
+            TestReq::validate(b"version 2").unwrap_err(),
+            Error::NotWritable
+        );
+        assert_eq!(TestReq::validate(b"save?").unwrap_err(), Error::NotReadable);
+        assert_eq!(
+            TestReq::validate(b"setlimits").unwrap_err(),
+            Error::NotReadable
+        );
+        assert_eq!(TestReq::validate(b"VERSION").unwrap().name, "version");
+        assert_eq!(TestReq::validate(b"lim 1").unwrap().name, "setlimits");
+    }
+
+    #[test]
+    fn test_resolve() {
+        init_logger();
+        let name = |n: &[u8]| resolve(TestReq::COMMANDS, n).map(|c| c.name);
+        assert_eq!(name(b"version"), Ok("version"));
+        assert_eq!(name(b"Version"), Ok("version"));
+        assert_eq!(name(b"LIMITS"), Ok("setlimits"));
+        assert_eq!(name(b"sa"), Ok("save"));
+        assert_eq!(name(b"SETL"), Ok("setlimits"));
+        // Prefixes of several names of the same command are not ambiguous.
+        assert_eq!(name(b"li"), Ok("setlimits"));
+        assert_eq!(name(b"s"), Err(Error::AmbiguousCommand));
+        assert_eq!(name(b"x"), Err(Error::UnknownCommand));
+        assert_eq!(name(b""), Err(Error::UnknownCommand));
+    }
+
+    #[test]
+    fn test_relaxed_decoding() {
+        init_logger();
+        assert_eq!(
+            from_bytes_relaxed::<TestReq>(b"ILASER 1.5").unwrap(),
+            TestReq::LaserCurrent(Some(1.5))
+        );
+        assert_eq!(
+            from_bytes_relaxed::<TestReq>(b"Lim 1 2").unwrap(),
+            TestReq::SetLimits {
+                rtmin: 1.0,
+                rtmax: Some(2.0)
+            }
+        );
+        // Aliases also work when decoding strictly, other names do not.
+        assert_eq!(
+            from_bytes::<TestReq>(b"limits 1").unwrap(),
+            TestReq::SetLimits {
+                rtmin: 1.0,
+                rtmax: None
+            }
+        );
+        assert!(from_bytes::<TestReq>(b"VERSION").is_err());
+
+        let e = from_bytes_relaxed::<TestReq>(b"  s 1").unwrap_err();
+        assert_eq!(e.error, Error::AmbiguousCommand);
+        assert_eq!(e.context.offset, 2);
+        assert_eq!(e.context.expected, Some(Expected::VariantName));
+    }
+}
@@ -1,51 +1,123 @@
 use core::fmt;
 
+use embedded_io::ErrorKind;
+use heapless::String;
 use serde::{de, ser, Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Longest command name kept in an [`ErrorReport`]; longer names are cut.
+pub const COMMAND_NAME_SIZE: usize = 16;
+
+// Declares `Error` with its codes and decodes the same codes in `TryFrom<u16>`, so the two
+// cannot drift apart.
+macro_rules! error_codes {
+    (
+        $(#[$attr:meta])*
+        pub enum $ty:ident {
+            $($variant:ident = $code:literal,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(u16)]
+        pub enum $ty {
+            $($variant = $code,)*
+        }
+
+        impl TryFrom<u16> for $ty {
+            type Error = UnknownErrorCode;
+
+            fn try_from(code: u16) -> core::result::Result<Self, UnknownErrorCode> {
+                match code {
+                    $($code => Ok($ty::$variant),)*
+                    _ => Err(UnknownErrorCode(code)),
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// Everything that can go wrong, with the code sent to the controller (`error as u16`).
+    ///
+    /// Codes are part of the protocol and never change once released. They are grouped by
+    /// subsystem, and new variants take the next free code of their range:
+    ///
+    /// | Range             | Subsystem                           |
+    /// |-------------------|-------------------------------------|
+    /// | `0x0001..0x0100`  | UART and device link                |
+    /// | `0x1000..0x1100`  | Parsing values in device responses  |
+    /// | `0x2000..0x2100`  | Application                         |
+    /// | `0x3000..0x3100`  | (De)serialization and framing       |
+    /// | `0x4000..0x4100`  | Ferox requests and commands         |
+    ///
+    /// Codes released before the ranges keep their original values, so `0x2001..0x200C` holds
+    /// (de)serialization, request and UART timeout errors too.
+    #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum Error {
+        // UART and device link
+        BufferOverflow = 0x0001,
+        DeviceError = 0x0002,
+        EchoMismatch = 0x0003,
+        FlushError = 0x0004,
+        InvalidResponse = 0x0005,
+        ReadError = 0x0006,
+        WriteError = 0x0007,
+        WriteErrorInTryOnce = 0x0011,
+        WriteErrorInWriteLine = 0x0012,
+        WriteErrorInCtl200Query = 0x0013,
+        FormatErrorInWriteResponse = 0x0014,
+        FormatErrorInWriteError = 0x0015,
+
+        // Parsing values in device responses
+        BytesToUTF8Error = 0x1000,
+        InvalidBoolean = 0x1001,
+        ParseIntError = 0x1002,
+        ParseFloatError = 0x1003,
+
+        // Application, and codes released before the ranges
+        InvalidFirmwareVersion = 0x2000,
+        EndOfFile = 0x2001,
+        Utf8Error = 0x2002,
+        ParseI8Error = 0x2003,
+        UnexpectedToken = 0x2004,
+        InvalidRequest = 0x2005,
+        InvalidRequestForDeserialize = 0x2006,
+        InvalidRequestForSerialize = 0x2007,
+        NotSupportedInSerializing = 0x2008,
+        Ctl200RequestSerializeError = 0x2009,
+        SmcRequestSerializeError = 0x200A,
+        UartRequestTimeout = 0x200B,
+
+        // (De)serialization and framing
+        IntegerOverflow = 0x3000,
+        InvalidLength = 0x3001,
+        TrailingCharacters = 0x3002,
+        UnterminatedString = 0x3003,
+        InvalidEscape = 0x3004,
+        InvalidFrame = 0x3005,
+        CrcMismatch = 0x3006,
+        NumberTooLong = 0x3007,
+
+        // Ferox requests and commands
+        UnknownCommand = 0x4000,
+        NotReadable = 0x4001,
+        NotWritable = 0x4002,
+        AmbiguousCommand = 0x4003,
+
+        // There should be no errors after PlaceHolder.
+        PlaceHolder = 0xFFFF,
+    }
+}
+
+/// A code that no [`Error`] has, e.g. from newer firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub enum Error {
-    // TODO(xguo): Reorganize errors.
-    BufferOverflow = 1,
-    DeviceError,
-    EchoMismatch,
-    FlushError,
-    InvalidResponse,
-    ReadError,
-    WriteError,
-
-    WriteErrorInTryOnce = 0x11,
-    WriteErrorInWriteLine,
-    WriteErrorInCtl200Query,
-    FormatErrorInWriteResponse,
-    FormatErrorInWriteError,
-
-    BytesToUTF8Error = 0x1000,
-    InvalidBoolean,
-    ParseIntError,
-    ParseFloatError,
-
-    // Used by application
-    InvalidFirmwareVersion = 0x2000,
-
-    // (De)Serialization errors
-    EndOfFile,
-    Utf8Error,
-    ParseI8Error,
-    UnexpectedToken,
-
-    // Ferox Request related
-    InvalidRequest,
-    InvalidRequestForDeserialize,
-    InvalidRequestForSerialize,
-    NotSupportedInSerializing,
-    Ctl200RequestSerializeError,
-    SmcRequestSerializeError,
-
-    UartRequestTimeout,
-
-    // There should be no errors after PlaceHolder.
-    PlaceHolder = 0xFFFF,
+pub struct UnknownErrorCode(pub u16);
+
+impl fmt::Display for UnknownErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown error code 0x{:04X}", self.0)
+    }
 }
 
 #[cfg(not(feature = "full-display"))]
@@ -76,6 +148,14 @@ impl core::fmt::Display for Error {
             Error::Utf8Error => write!(f, "UTF-8 error"),
             Error::ParseI8Error => write!(f, "Parse i8 error"),
             Error::UnexpectedToken => write!(f, "Unexpected token"),
+            Error::IntegerOverflow => write!(f, "Integer out of range"),
+            Error::InvalidLength => write!(f, "Invalid length"),
+            Error::TrailingCharacters => write!(f, "Trailing characters"),
+            Error::UnterminatedString => write!(f, "Unterminated string"),
+            Error::InvalidEscape => write!(f, "Invalid escape"),
+            Error::InvalidFrame => write!(f, "Invalid frame"),
+            Error::CrcMismatch => write!(f, "CRC mismatch"),
+            Error::NumberTooLong => write!(f, "Number too long"),
             Error::InvalidRequest => write!(f, "Invalid request"),
             Error::PlaceHolder => write!(f, "Placeholder error"),
             Error::InvalidRequestForDeserialize => write!(f, "Invalid request for deserialize"),
@@ -89,12 +169,123 @@ impl core::fmt::Display for Error {
             Error::FormatErrorInWriteError => write!(f, "Format error in write error"),
             Error::Ctl200RequestSerializeError => write!(f, "CTL200 request serialization error"),
             Error::SmcRequestSerializeError => write!(f, "SMC request serialization error"),
+            Error::UnknownCommand => write!(f, "Unknown command"),
+            Error::NotReadable => write!(f, "Command cannot be read"),
+            Error::NotWritable => write!(f, "Command cannot be written"),
+            Error::AmbiguousCommand => write!(f, "Ambiguous command"),
         }
     }
 }
 
 impl core::error::Error for Error {}
 
+/// An [`Error`] together with where it happened: which device, which command, which attempt,
+/// and what the UART reported.
+///
+/// Only [`ErrorReport::code`] goes over the wire; the rest is for logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorReport {
+    pub error: Error,
+    /// The device or port, e.g. `ctl200`.
+    pub device: Option<&'static str>,
+    /// The first word of the request.
+    pub command: Option<String<COMMAND_NAME_SIZE>>,
+    /// The attempt that failed, counting from 1.
+    pub attempt: Option<u32>,
+    /// The kind of the underlying I/O error.
+    pub io: Option<ErrorKind>,
+}
+
+impl ErrorReport {
+    pub fn new(error: Error) -> Self {
+        Self {
+            error,
+            device: None,
+            command: None,
+            attempt: None,
+            io: None,
+        }
+    }
+
+    /// An I/O failure reported as `error`, keeping the kind of `e`.
+    pub fn from_io<E: embedded_io::Error>(error: Error, e: E) -> Self {
+        Self::new(error).with_io(e.kind())
+    }
+
+    /// The code sent to the controller, the same as `error as u16`.
+    pub fn code(&self) -> u16 {
+        self.error as u16
+    }
+
+    pub fn with_device(mut self, device: &'static str) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Records the command of `request`, i.e. everything up to the first space or `?`.
+    pub fn with_command(mut self, request: &[u8]) -> Self {
+        let end = request
+            .iter()
+            .position(|&b| b == b' ' || b == b'?')
+            .unwrap_or(request.len());
+        let name = match core::str::from_utf8(&request[..end]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&request[..e.valid_up_to()]).unwrap_or_default(),
+        };
+        let mut command = String::new();
+        for c in name.chars() {
+            if command.push(c).is_err() {
+                break;
+            }
+        }
+        self.command = Some(command);
+        self
+    }
+
+    pub fn with_attempt(mut self, attempt: u32) -> Self {
+        self.attempt = Some(attempt);
+        self
+    }
+
+    pub fn with_io(mut self, kind: ErrorKind) -> Self {
+        self.io = Some(kind);
+        self
+    }
+}
+
+impl From<Error> for ErrorReport {
+    fn from(error: Error) -> Self {
+        Self::new(error)
+    }
+}
+
+impl From<ErrorReport> for Error {
+    fn from(report: ErrorReport) -> Self {
+        report.error
+    }
+}
+
+/// Prints e.g. `UART request timeout on ctl200 running `version` (attempt 3)`.
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(device) = self.device {
+            write!(f, " on {}", device)?;
+        }
+        if let Some(command) = &self.command {
+            write!(f, " running `{}`", command)?;
+        }
+        if let Some(attempt) = self.attempt {
+            write!(f, " (attempt {})", attempt)?;
+        }
+        if let Some(io) = self.io {
+            write!(f, ": {:?}", io)?;
+        }
+        Ok(())
+    }
+}
+
 impl ser::Error for Error {
     fn custom<T>(_msg: T) -> Self
     where
@@ -111,4 +302,119 @@ impl de::Error for Error {
     {
         Error::InvalidRequestForDeserialize
     }
+
+    // Raised by bounded containers such as `heapless::Vec` when there are more elements than fit.
+    fn invalid_length(_len: usize, _exp: &dyn de::Expected) -> Self {
+        Error::InvalidLength
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    // Spells out every code, so that changing one fails here and adding a variant does not compile
+    // until its code is listed.
+    fn pinned_code(error: Error) -> u16 {
+        match error {
+            Error::BufferOverflow => 0x0001,
+            Error::DeviceError => 0x0002,
+            Error::EchoMismatch => 0x0003,
+            Error::FlushError => 0x0004,
+            Error::InvalidResponse => 0x0005,
+            Error::ReadError => 0x0006,
+            Error::WriteError => 0x0007,
+            Error::WriteErrorInTryOnce => 0x0011,
+            Error::WriteErrorInWriteLine => 0x0012,
+            Error::WriteErrorInCtl200Query => 0x0013,
+            Error::FormatErrorInWriteResponse => 0x0014,
+            Error::FormatErrorInWriteError => 0x0015,
+            Error::BytesToUTF8Error => 0x1000,
+            Error::InvalidBoolean => 0x1001,
+            Error::ParseIntError => 0x1002,
+            Error::ParseFloatError => 0x1003,
+            Error::InvalidFirmwareVersion => 0x2000,
+            Error::EndOfFile => 0x2001,
+            Error::Utf8Error => 0x2002,
+            Error::ParseI8Error => 0x2003,
+            Error::UnexpectedToken => 0x2004,
+            Error::InvalidRequest => 0x2005,
+            Error::InvalidRequestForDeserialize => 0x2006,
+            Error::InvalidRequestForSerialize => 0x2007,
+            Error::NotSupportedInSerializing => 0x2008,
+            Error::Ctl200RequestSerializeError => 0x2009,
+            Error::SmcRequestSerializeError => 0x200A,
+            Error::UartRequestTimeout => 0x200B,
+            Error::IntegerOverflow => 0x3000,
+            Error::InvalidLength => 0x3001,
+            Error::TrailingCharacters => 0x3002,
+            Error::UnterminatedString => 0x3003,
+            Error::InvalidEscape => 0x3004,
+            Error::InvalidFrame => 0x3005,
+            Error::CrcMismatch => 0x3006,
+            Error::NumberTooLong => 0x3007,
+            Error::UnknownCommand => 0x4000,
+            Error::NotReadable => 0x4001,
+            Error::NotWritable => 0x4002,
+            Error::AmbiguousCommand => 0x4003,
+            Error::PlaceHolder => 0xFFFF,
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        init_logger();
+        let mut decoded = 0;
+        for code in 0..=u16::MAX {
+            if let Ok(error) = Error::try_from(code) {
+                assert_eq!(error as u16, code);
+                assert_eq!(pinned_code(error), code, "code of {:?} changed", error);
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 41);
+        assert_eq!(Error::try_from(0x0000), Err(UnknownErrorCode(0x0000)));
+        assert_eq!(Error::try_from(0x3008), Err(UnknownErrorCode(0x3008)));
+    }
+
+    #[test]
+    fn test_error_report() {
+        init_logger();
+        let report = ErrorReport::new(Error::UartRequestTimeout)
+            .with_device("ctl200")
+            .with_command(b"ilaser 12.5")
+            .with_attempt(3)
+            .with_io(ErrorKind::TimedOut);
+        assert_eq!(report.code(), Error::UartRequestTimeout as u16);
+        assert_eq!(Error::from(report.clone()), Error::UartRequestTimeout);
+        assert_eq!(
+            report.to_string(),
+            "UART request timeout on ctl200 running `ilaser` (attempt 3): TimedOut"
+        );
+        assert_eq!(
+            ErrorReport::from(Error::ReadError).to_string(),
+            "Read error"
+        );
+    }
+
+    #[test]
+    fn test_error_report_command() {
+        init_logger();
+        let command = |request: &[u8]| {
+            ErrorReport::new(Error::ReadError)
+                .with_command(request)
+                .command
+        };
+        assert_eq!(command(b"ilaser?").unwrap(), "ilaser");
+        assert_eq!(command(b"").unwrap(), "");
+        assert_eq!(
+            command(b"averyveryverylongcommand 1").unwrap(),
+            "averyveryverylon"
+        );
+        assert_eq!(command(b"v\xffx").unwrap(), "v");
+    }
 }
@@ -1,15 +1,38 @@
 use serde::{Deserialize, Serialize};
 
+use crate::proto::command::ascii_command;
+
+#[ascii_command]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum FeroxRequest {
-    #[serde(rename = "allver")]
+    /// Firmware versions of the CTL200 and the SMC.
+    #[command(name = "allver", alias = "versions", alias = "ver")]
     AllVersions,
+
+    /// Lists the available commands.
+    Help,
 }
 
+#[ascii_command]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SmcRequest<'a> {
-    #[serde(rename = "bia")]
+    /// Firmware version.
+    #[command(name = "bia", get)]
     Version(Option<&'a [u8]>),
 }
+
+/// Responses to [`FeroxRequest`]s sent in binary frames, see [`crate::proto::binary`].
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FeroxResponse<'a> {
+    AllVersions {
+        ctl200: &'a str,
+        smc: &'a str,
+    },
+    /// One line of text, e.g. of the `help` listing.
+    Text(&'a str),
+    /// The request failed with this error code.
+    Error(u16),
+}
@@ -9,3 +9,82 @@ pub fn init_logger() {
         env_logger::builder().is_test(true).try_init().unwrap();
     });
 }
+
+/// Hands out one chunk per read, or as much of it as fits.
+pub struct ChunkedReader(pub std::vec::Vec<&'static [u8]>);
+
+impl ChunkedReader {
+    /// Hands out `data` at most `size` bytes per read.
+    pub fn every(data: &'static [u8], size: usize) -> Self {
+        Self(data.chunks(size).collect())
+    }
+}
+
+impl embedded_io_async::ErrorType for ChunkedReader {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_io_async::Read for ChunkedReader {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        let chunk = self.0[0];
+        let n = chunk.len().min(buf.len());
+        buf[..n].copy_from_slice(&chunk[..n]);
+        if n == chunk.len() {
+            self.0.remove(0);
+        } else {
+            self.0[0] = &chunk[n..];
+        }
+        Ok(n)
+    }
+}
+
+/// Runs `embassy_time` on the host clock, so that timeouts and timers work in tests. Every
+/// wake-up gets its own thread, which is fine for the few timers a test starts.
+struct StdTime;
+
+fn host_now() -> u64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    let elapsed = START.get_or_init(std::time::Instant::now).elapsed();
+    (elapsed.as_micros() * u128::from(embassy_time_driver::TICK_HZ) / 1_000_000) as u64
+}
+
+impl embassy_time_driver::Driver for StdTime {
+    fn now(&self) -> u64 {
+        host_now()
+    }
+
+    // Timers go through the queue below, which needs no alarms.
+    unsafe fn allocate_alarm(&self) -> Option<embassy_time_driver::AlarmHandle> {
+        None
+    }
+
+    fn set_alarm_callback(
+        &self,
+        _alarm: embassy_time_driver::AlarmHandle,
+        _callback: fn(*mut ()),
+        _ctx: *mut (),
+    ) {
+    }
+
+    fn set_alarm(&self, _alarm: embassy_time_driver::AlarmHandle, _timestamp: u64) -> bool {
+        false
+    }
+}
+
+impl embassy_time_queue_driver::TimerQueue for StdTime {
+    fn schedule_wake(&'static self, at: u64, waker: &core::task::Waker) {
+        let waker = waker.clone();
+        std::thread::spawn(move || {
+            let ticks = at.saturating_sub(host_now());
+            let micros = u128::from(ticks) * 1_000_000 / u128::from(embassy_time_driver::TICK_HZ);
+            std::thread::sleep(std::time::Duration::from_micros(micros as u64));
+            waker.wake();
+        });
+    }
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: StdTime = StdTime);
+embassy_time_queue_driver::timer_queue_impl!(static QUEUE: StdTime = StdTime);
@@ -1,56 +1,119 @@
+pub mod echo;
+pub mod framed;
 pub mod post_processor;
+pub mod protocol;
+pub mod retry;
 
-use core::cmp::min;
+use core::{
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+};
 
-use defmt_or_log::debug;
-use embassy_time::Duration;
+use defmt_or_log::{debug, error};
+use echo::{strip_echo, EchoMode};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_io_async::{Read, Write};
+use framed::{EndPattern, FramedReader};
 use post_processor::PostProcessor;
+use protocol::Protocol;
+use retry::RetryPolicy;
 
-use crate::proto::{error::Error as FeroxError, Result as FeroxResult};
+use crate::{
+    proto::{
+        binary::FRAME_END,
+        error::{Error as FeroxError, ErrorReport},
+        Result as FeroxResult,
+    },
+    MAX_STRING_SIZE,
+};
 
-// TODO(xguo): test the function.
-pub async fn read_until<R: Read>(
-    reader: &mut R,
-    buf: &mut [u8],
-    terminator: &[u8],
-) -> FeroxResult<usize> {
-    const TEMP_SIZE: usize = 64;
-    let buf_len = buf.len();
-    let mut temp = [0u8; TEMP_SIZE];
-    let mut pos = 0;
-
-    while pos + terminator.len() <= buf.len() {
-        let chunk_size = min(TEMP_SIZE, buf_len - pos);
-        let sz = reader
-            .read(&mut temp[..chunk_size])
-            .await
-            .map_err(|_| FeroxError::ReadError)?;
-        if sz == 0 {
-            return Err(FeroxError::ReadError);
-        }
-        debug!(
-            "Read {} bytes: {:?}",
-            sz,
-            core::str::from_utf8(&temp[..sz]).unwrap_or("<invalid utf8>")
-        );
-        buf[pos..pos + sz].copy_from_slice(&temp[..sz]);
-        pos += sz;
+// How long the line must stay quiet before stale bytes count as drained.
+const DRAIN_IDLE: Duration = Duration::from_millis(10);
+// Longest time spent draining a line that does not go quiet.
+const DRAIN_LIMIT: Duration = Duration::from_millis(100);
 
-        if pos >= terminator.len() {
-            let start_idx = pos - terminator.len();
-            if &buf[start_idx..pos] == terminator {
-                return Ok(pos - terminator.len());
-            }
+/// Drops the bytes `reader` has already received, without waiting for more, and returns how
+/// many there were.
+pub async fn drain<R: Read>(reader: &mut R) -> usize {
+    let mut scratch = [0u8; 16];
+    let mut dropped = 0;
+    loop {
+        let mut read = pin!(reader.read(&mut scratch));
+        // Polled once: a read that would wait means nothing is pending.
+        let n = poll_fn(|cx| match read.as_mut().poll(cx) {
+            Poll::Ready(Ok(n)) => Poll::Ready(n),
+            Poll::Ready(Err(_)) | Poll::Pending => Poll::Ready(0),
+        })
+        .await;
+        if n == 0 {
+            return dropped;
         }
+        dropped += n;
     }
+}
 
-    Err(FeroxError::BufferOverflow)
+/// How a request was framed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Framing {
+    /// A line of text.
+    Ascii,
+    /// A binary frame, see [`crate::proto::binary`].
+    Binary,
+}
+
+/// Reads one request that is either a line ending in `line_end` or a binary frame ending in
+/// [`FRAME_END`], and returns which one it was together with its length without the terminator.
+///
+/// Whichever terminator comes first ends the request. Bytes after it stay in `framed`, so
+/// requests sent back to back are read one by one. Zero bytes before a request are skipped, so
+/// a binary peer may send one to resynchronize.
+pub async fn read_request<R: Read, const N: usize>(
+    framed: &mut FramedReader<N>,
+    reader: &mut R,
+    buf: &mut [u8],
+    line_end: &[u8],
+) -> FeroxResult<(Framing, usize), ErrorReport> {
+    const BINARY: usize = 0;
+    let frame_end = [FRAME_END];
+    let patterns = [EndPattern::new(&frame_end), EndPattern::new(line_end)];
+    loop {
+        let (index, frame) = framed.read_frame_any(reader, &patterns).await?;
+        if index == BINARY && frame.is_empty() {
+            continue;
+        }
+        buf.get_mut(..frame.len())
+            .ok_or(FeroxError::BufferOverflow)?
+            .copy_from_slice(frame);
+        let framing = if index == BINARY {
+            Framing::Binary
+        } else {
+            Framing::Ascii
+        };
+        return Ok((framing, frame.len()));
+    }
 }
 
 pub struct UartWrapper<UART, P> {
     uart: UART,
     post_processor: P,
+    // Names the port in error reports.
+    device: Option<&'static str>,
+    // Keeps what the device sent after the last response.
+    reader: FramedReader<MAX_STRING_SIZE>,
+    // Attempts taken by the last query.
+    attempts: u32,
+    // What the device prints when it is ready for a command, see `resync`.
+    prompt: Option<&'static [u8]>,
+    echo: EchoMode,
+    // Sent after every request and line.
+    line_ending: &'static [u8],
+    // Ends every response to `query`.
+    terminator: &'static [u8],
+    // A `\r` written through `Write` as the last byte of a buffer, held back in case a `\n`
+    // follows.
+    pending_cr: bool,
 }
 
 impl<UART, P> UartWrapper<UART, P>
@@ -59,9 +122,56 @@ where
     P: PostProcessor,
 {
     pub fn new(uart: UART, post_processor: P) -> Self {
+        Self::with_protocol(uart, Protocol::new(post_processor))
+    }
+
+    /// Talks to the device as `protocol` says, e.g. [`protocol::ctl200`].
+    pub fn with_protocol(uart: UART, protocol: Protocol<P>) -> Self {
         Self {
             uart,
-            post_processor,
+            post_processor: protocol.post_processor,
+            device: None,
+            reader: FramedReader::new(),
+            attempts: 0,
+            prompt: None,
+            echo: protocol.echo,
+            line_ending: protocol.line_ending,
+            terminator: protocol.terminator,
+            pending_cr: false,
+        }
+    }
+
+    /// Number of attempts the last query took, successful or not.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Names the device on this port, e.g. `ctl200`, in error reports.
+    pub fn with_device(mut self, device: &'static str) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Sets how the device echoes requests. The echo is removed before the post-processor
+    /// sees the response.
+    pub fn with_echo(mut self, mode: EchoMode) -> Self {
+        self.echo = mode;
+        self
+    }
+
+    /// Resynchronizes the link with [`UartWrapper::resync`] after every timeout, waiting for
+    /// `prompt`, e.g. `\r\n>>`.
+    pub fn with_resync(mut self, prompt: &'static [u8]) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    // Adds the device, command and attempt to a failure of `try_once`.
+    fn report(&self, report: ErrorReport, request: &[u8], attempt: u32) -> ErrorReport {
+        let report = report.with_command(request).with_attempt(attempt);
+        match self.device {
+            Some(device) => report.with_device(device),
+            None => report,
         }
     }
 
@@ -69,28 +179,48 @@ where
         &mut self,
         request: &[u8],
         response_buf: &mut [u8],
-        terminator: &[u8],
+        patterns: &[EndPattern<'_>],
         timeout: Duration,
-    ) -> FeroxResult<usize> {
+    ) -> FeroxResult<(usize, usize), ErrorReport> {
+        self.drain().await;
         self.uart
             .write_all(request)
             .await
-            .map_err(|_| FeroxError::WriteErrorInTryOnce)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::WriteErrorInTryOnce, e))?;
         self.uart
-            .write_all(b"\r\n")
+            .write_all(self.line_ending)
             .await
-            .map_err(|_| FeroxError::WriteErrorInTryOnce)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::WriteErrorInTryOnce, e))?;
         self.uart
             .flush()
             .await
-            .map_err(|_| FeroxError::FlushError)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::FlushError, e))?;
 
-        embassy_time::with_timeout(
+        let (index, frame) = embassy_time::with_timeout(
             timeout,
-            read_until(&mut self.uart, response_buf, terminator),
+            self.reader.read_frame_any(&mut self.uart, patterns),
         )
         .await
-        .map_err(|_| FeroxError::UartRequestTimeout)?
+        .map_err(|_| ErrorReport::new(FeroxError::UartRequestTimeout))??;
+        let frame = strip_echo(self.echo, request, frame)?;
+        response_buf
+            .get_mut(..frame.len())
+            .ok_or(FeroxError::BufferOverflow)?
+            .copy_from_slice(frame);
+        Ok((index, frame.len()))
+    }
+
+    /// Sends `request` and waits for a response that ends with the terminator of the
+    /// protocol, see [`UartWrapper::query_with_pattern`].
+    pub async fn query<'a>(
+        &mut self,
+        request: &[u8],
+        response_buf: &'a mut [u8],
+        timeout: Duration,
+        retry: &impl RetryPolicy,
+    ) -> FeroxResult<&'a [u8], ErrorReport> {
+        self.query_with_pattern(request, self.terminator, response_buf, timeout, retry)
+            .await
     }
 
     pub async fn query_with_pattern<'a>(
@@ -99,43 +229,158 @@ where
         terminator: &[u8],
         response_buf: &'a mut [u8],
         timeout: Duration,
-        max_retries: i32,
-    ) -> FeroxResult<&'a [u8]> {
-        for attempt in 1..=max_retries {
+        retry: &impl RetryPolicy,
+    ) -> FeroxResult<&'a [u8], ErrorReport> {
+        let patterns = [EndPattern::new(terminator)];
+        let (_, response) = self
+            .query_with_patterns(request, &patterns, response_buf, timeout, retry)
+            .await?;
+        Ok(response)
+    }
+
+    /// Sends `request` and waits for a response that ends with any of `patterns`, e.g. a
+    /// prompt or an error banner. Returns the index of the pattern that matched, see
+    /// [`FramedReader::read_frame_any`], and the post-processed response.
+    ///
+    /// Failed attempts are repeated as long as `retry` allows, after dropping whatever the
+    /// device sent in the meantime.
+    pub async fn query_with_patterns<'a>(
+        &mut self,
+        request: &[u8],
+        patterns: &[EndPattern<'_>],
+        response_buf: &'a mut [u8],
+        timeout: Duration,
+        retry: &impl RetryPolicy,
+    ) -> FeroxResult<(usize, &'a [u8]), ErrorReport> {
+        let mut attempt = 1;
+        loop {
+            self.attempts = attempt;
             match self
-                .try_once(request, response_buf, terminator, timeout)
+                .try_once(request, response_buf, patterns, timeout)
                 .await
             {
-                Ok(size) => {
-                    debug!("Query succeeded on attempt {}", attempt);
-                    let processed_data = self.post_processor.post_process(&response_buf[..size]);
-                    return Ok(processed_data);
+                Ok((index, size)) => {
+                    debug!(
+                        "Query succeeded on attempt {} with pattern {}",
+                        attempt, index
+                    );
+                    // Post-processing failures mean the device did answer, so they are not
+                    // retried.
+                    return match self
+                        .post_processor
+                        .post_process(request, &response_buf[..size])
+                    {
+                        Ok(processed_data) => Ok((index, processed_data)),
+                        Err(e) => {
+                            let report = self.report(e.into(), request, attempt);
+                            error!("Query failed: {}", report);
+                            Err(report)
+                        }
+                    };
                 }
                 Err(e) => {
                     debug!("Error during attempt {}: {:?}", attempt, e);
-                    if attempt == max_retries {
-                        debug!("Max retries reached. Failing with error: {:?}", e);
-                        return Err(e);
+                    let delay = retry.retry(request, attempt, &e);
+                    match self.prompt {
+                        Some(prompt) if e.error == FeroxError::UartRequestTimeout => {
+                            if let Err(e) = self.resync(prompt, timeout).await {
+                                debug!("Resync failed: {:?}", e);
+                            }
+                        }
+                        _ => self.drain_idle().await,
                     }
+                    let Some(delay) = delay else {
+                        let report = self.report(e, request, attempt);
+                        error!("Query failed: {}", report);
+                        return Err(report);
+                    };
+                    Timer::after(delay).await;
+                    attempt += 1;
                 }
             }
         }
-        Err(FeroxError::UartRequestTimeout) // 理论上不会到这里
     }
 
-    pub async fn write_line(&mut self, line: &str) -> FeroxResult<()> {
+    /// Sends a blank line and waits up to `timeout` for `prompt`, then drops anything else the
+    /// device sends. Brings the link back to a known state after a timeout or garbage.
+    pub async fn resync(
+        &mut self,
+        prompt: &[u8],
+        timeout: Duration,
+    ) -> FeroxResult<(), ErrorReport> {
+        debug!("Resynchronizing");
+        self.drain().await;
+        self.write_line("").await?;
+        embassy_time::with_timeout(timeout, self.reader.read_frame(&mut self.uart, prompt))
+            .await
+            .map_err(|_| ErrorReport::new(FeroxError::UartRequestTimeout))??;
+        self.drain_idle().await;
+        Ok(())
+    }
+
+    // Drops everything received so far, including what is left over from the last response.
+    async fn drain(&mut self) {
+        self.reader.clear();
+        let n = drain(&mut self.uart).await;
+        if n > 0 {
+            debug!("Dropped {} stale bytes", n);
+        }
+    }
+
+    // Drops what the device sends until the line stays quiet, e.g. the late response to a
+    // request that timed out, so that it is not taken for the response to the next attempt.
+    // Gives up after `DRAIN_LIMIT` so that a device that never stops talking cannot stall us.
+    async fn drain_idle(&mut self) {
+        self.reader.clear();
+        let deadline = Instant::now() + DRAIN_LIMIT;
+        let mut scratch = [0u8; 16];
+        while Instant::now() < deadline {
+            match embassy_time::with_timeout(DRAIN_IDLE, self.uart.read(&mut scratch)).await {
+                Ok(Ok(n @ 1..)) => debug!("Dropped {} stale bytes", n),
+                _ => return,
+            }
+        }
+        debug!(
+            "Line still busy after draining for {} ms",
+            DRAIN_LIMIT.as_millis()
+        );
+    }
+
+    /// Reads one request from the port, see [`read_request`].
+    pub async fn read_request(
+        &mut self,
+        buf: &mut [u8],
+        line_end: &[u8],
+    ) -> FeroxResult<(Framing, usize), ErrorReport> {
+        read_request(&mut self.reader, &mut self.uart, buf, line_end).await
+    }
+
+    pub async fn write_line(&mut self, line: &str) -> FeroxResult<(), ErrorReport> {
         self.uart
             .write_all(line.as_bytes())
             .await
-            .map_err(|_| FeroxError::WriteErrorInWriteLine)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::WriteErrorInWriteLine, e))?;
         self.uart
-            .write_all(b"\r\n")
+            .write_all(self.line_ending)
             .await
-            .map_err(|_| FeroxError::WriteErrorInWriteLine)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::WriteErrorInWriteLine, e))?;
         self.uart
             .flush()
             .await
-            .map_err(|_| FeroxError::FlushError)?;
+            .map_err(|e| ErrorReport::from_io(FeroxError::FlushError, e))?;
+        Ok(())
+    }
+
+    /// Writes `data` as is, e.g. a binary frame, and flushes.
+    pub async fn write_bytes(&mut self, data: &[u8]) -> FeroxResult<(), ErrorReport> {
+        self.uart
+            .write_all(data)
+            .await
+            .map_err(|e| ErrorReport::from_io(FeroxError::WriteError, e))?;
+        self.uart
+            .flush()
+            .await
+            .map_err(|e| ErrorReport::from_io(FeroxError::FlushError, e))?;
         Ok(())
     }
 }
@@ -154,6 +399,432 @@ where
     P: PostProcessor,
 {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.uart.read(buf).await
+        // Bytes left over from the last response come first.
+        self.reader.chain(&mut self.uart).read(buf).await
+    }
+}
+
+impl<UART, P> embedded_io_async::BufRead for UartWrapper<UART, P>
+where
+    UART: Read + Write,
+    P: PostProcessor,
+{
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.reader.fill_buf(&mut self.uart).await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+/// Writes bytes as they are, except that each line break, `\n` or `\r\n`, is sent as the line
+/// ending of the protocol, like [`UartWrapper::write_line`] does. This lets generic writers such
+/// as `core::fmt` adapters produce lines the device accepts.
+impl<UART, P> embedded_io_async::Write for UartWrapper<UART, P>
+where
+    UART: Read + Write,
+    P: PostProcessor,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let Some(&first) = buf.first() else {
+            return Ok(0);
+        };
+        if core::mem::take(&mut self.pending_cr) {
+            if first == b'\n' {
+                self.uart.write_all(self.line_ending).await?;
+                return Ok(1);
+            }
+            self.uart.write_all(b"\r").await?;
+        }
+        match (first, buf.get(1)) {
+            (b'\n', _) => {
+                self.uart.write_all(self.line_ending).await?;
+                Ok(1)
+            }
+            (b'\r', Some(b'\n')) => {
+                self.uart.write_all(self.line_ending).await?;
+                Ok(2)
+            }
+            (b'\r', None) => {
+                self.pending_cr = true;
+                Ok(1)
+            }
+            (b'\r', Some(_)) => {
+                self.uart.write_all(b"\r").await?;
+                Ok(1)
+            }
+            _ => {
+                let end = buf
+                    .iter()
+                    .position(|&b| b == b'\r' || b == b'\n')
+                    .unwrap_or(buf.len());
+                self.uart.write(&buf[..end]).await
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        if core::mem::take(&mut self.pending_cr) {
+            self.uart.write_all(b"\r").await?;
+        }
+        self.uart.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{
+        testing::helpers::{init_logger, ChunkedReader},
+        uart::{
+            post_processor::DefaultPostProcessor,
+            retry::{ExceptCommands, Fixed, OnlyOnTimeout},
+        },
+    };
+
+    // Fails every read with the given kind.
+    struct FailingReader(embedded_io::ErrorKind);
+
+    impl embedded_io_async::ErrorType for FailingReader {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl Read for FailingReader {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Err(self.0)
+        }
+    }
+
+    // Replays `rx` and records what is written.
+    struct Port {
+        rx: ChunkedReader,
+        tx: std::vec::Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Port {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Port {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.rx.read(buf).await
+        }
+    }
+
+    impl Write for Port {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn port(rx: std::vec::Vec<&'static [u8]>) -> Port {
+        Port {
+            rx: ChunkedReader(rx),
+            tx: std::vec::Vec::new(),
+        }
+    }
+
+    // Answers each line written to it with the next of `replies`, `None` being no answer at
+    // all. Reads wait for as long as there is nothing to read, like a real port.
+    struct Device {
+        replies: std::collections::VecDeque<Option<&'static [u8]>>,
+        rx: std::vec::Vec<u8>,
+        tx: std::vec::Vec<u8>,
+        line: std::vec::Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Device {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Device {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.rx.is_empty() {
+                core::future::pending::<()>().await;
+            }
+            let n = buf.len().min(self.rx.len());
+            buf[..n].copy_from_slice(&self.rx[..n]);
+            self.rx.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Device {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            self.line.extend_from_slice(buf);
+            if self.line.ends_with(b"\r\n") {
+                self.line.clear();
+                if let Some(reply) = self.replies.pop_front().flatten() {
+                    self.rx.extend_from_slice(reply);
+                }
+            }
+            Ok(buf.len())
+        }
+    }
+
+    const PROMPT: &[u8] = b"\r\n>>";
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    // A device that ends every response with a prompt and does not echo.
+    fn device(
+        replies: std::vec::Vec<Option<&'static [u8]>>,
+    ) -> UartWrapper<Device, DefaultPostProcessor> {
+        let device = Device {
+            replies: replies.into(),
+            rx: std::vec::Vec::new(),
+            tx: std::vec::Vec::new(),
+            line: std::vec::Vec::new(),
+        };
+        UartWrapper::with_protocol(
+            device,
+            Protocol::new(DefaultPostProcessor).with_terminator(PROMPT),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_read_error_kind() {
+        init_logger();
+        let mut buf = [0u8; 16];
+        let mut reader = FailingReader(embedded_io::ErrorKind::InvalidData);
+        let report = FramedReader::<16>::new()
+            .read_frame(&mut reader, b"\r\n")
+            .await
+            .unwrap_err();
+        assert_eq!(report.error, FeroxError::ReadError);
+        assert_eq!(report.io, Some(embedded_io::ErrorKind::InvalidData));
+
+        let mut reader = FailingReader(embedded_io::ErrorKind::BrokenPipe);
+        let mut framed = FramedReader::<16>::new();
+        let report = read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+            .await
+            .unwrap_err();
+        assert_eq!(report.io, Some(embedded_io::ErrorKind::BrokenPipe));
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"stale\r\n>>", b"late"]);
+        assert_eq!(drain(&mut reader).await, 13);
+        assert_eq!(drain(&mut reader).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        init_logger();
+        let mut buf = [0u8; 16];
+
+        let mut reader = ChunkedReader(std::vec![b"all", b"ver\r\n"]);
+        let mut framed = FramedReader::<32>::new();
+        let (framing, len) = read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+            .await
+            .unwrap();
+        assert_eq!((framing, &buf[..len]), (Framing::Ascii, &b"allver"[..]));
+
+        let mut reader = ChunkedReader(std::vec![b"\0", b"\0\x02\x01", b"\x03\0"]);
+        let (framing, len) = read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            (framing, &buf[..len]),
+            (Framing::Binary, &b"\x02\x01\x03"[..])
+        );
+
+        let mut reader = ChunkedReader(std::vec![b"0123", b"4567", b"89"]);
+        let mut framed = FramedReader::<8>::new();
+        assert_eq!(
+            read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::BufferOverflow
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_pipelined_requests() {
+        init_logger();
+        let mut buf = [0u8; 16];
+        let mut framed = FramedReader::<32>::new();
+        // A binary frame, a line and another frame in one chunk, the last one split.
+        let mut reader = ChunkedReader(std::vec![
+            b"\x02\x01\0help\r\n\x03\x01",
+            b"\x02\0ver\r",
+            b"\n"
+        ]);
+        let mut requests = std::vec::Vec::new();
+        for _ in 0..4 {
+            let (framing, len) = read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+                .await
+                .unwrap();
+            requests.push((framing, buf[..len].to_vec()));
+        }
+        assert_eq!(
+            requests,
+            [
+                (Framing::Binary, b"\x02\x01".to_vec()),
+                (Framing::Ascii, b"help".to_vec()),
+                (Framing::Binary, b"\x03\x01\x02".to_vec()),
+                (Framing::Ascii, b"ver".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_request_too_long() {
+        init_logger();
+        let mut buf = [0u8; 4];
+        let mut framed = FramedReader::<32>::new();
+        let mut reader = ChunkedReader(std::vec![b"allver\r\nhelp\r\n"]);
+        assert_eq!(
+            read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::BufferOverflow
+        );
+        // The next request is still there.
+        let (framing, len) = read_request(&mut framed, &mut reader, &mut buf, b"\r\n")
+            .await
+            .unwrap();
+        assert_eq!((framing, &buf[..len]), (Framing::Ascii, &b"help"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_query_attempts() {
+        init_logger();
+        let mut wrapper = device(std::vec![None, None, None]);
+        let mut buf = [0u8; 16];
+        let policy = Fixed::new(3, Duration::from_millis(1));
+        let report = wrapper
+            .query(b"ilaser", &mut buf, TIMEOUT, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(report.error, FeroxError::UartRequestTimeout);
+        assert_eq!(report.attempt, Some(3));
+        assert_eq!(wrapper.attempts(), 3);
+        assert_eq!(wrapper.uart.tx, b"ilaser\r\nilaser\r\nilaser\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_query_only_on_timeout() {
+        init_logger();
+        // The device answers, but the echo is garbled.
+        let mut wrapper =
+            device(std::vec![Some(b"ilaset\r\n12.5\r\n>>"), None]).with_echo(EchoMode::Verify);
+        let mut buf = [0u8; 16];
+        let policy = OnlyOnTimeout(Fixed::new(3, Duration::from_millis(1)));
+        let report = wrapper
+            .query(b"ilaser", &mut buf, TIMEOUT, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(report.error, FeroxError::EchoMismatch);
+        assert_eq!(report.attempt, Some(1));
+        assert_eq!(wrapper.uart.tx, b"ilaser\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_query_except_commands() {
+        init_logger();
+        let mut wrapper = device(std::vec![None, Some(b"ok\r\n>>")]);
+        let mut buf = [0u8; 16];
+        let policy = ExceptCommands::new(&["save"], Fixed::new(3, Duration::from_millis(1)));
+        let report = wrapper
+            .query(b"save", &mut buf, TIMEOUT, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(report.error, FeroxError::UartRequestTimeout);
+        assert_eq!(report.attempt, Some(1));
+        assert_eq!(wrapper.uart.tx, b"save\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_query_drops_late_response() {
+        init_logger();
+        let mut wrapper = device(std::vec![None, Some(b"12.5\r\n>>")]);
+        let mut buf = [0u8; 16];
+        let policy = Fixed::new(1, Duration::MIN);
+        let report = wrapper
+            .query(b"version", &mut buf, TIMEOUT, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(report.error, FeroxError::UartRequestTimeout);
+        // The answer to `version` arrives after the query gave up.
+        wrapper.uart.rx.extend_from_slice(b"V0.17\r\n>>");
+        let response = wrapper.query(b"ilaser", &mut buf, TIMEOUT, &policy).await;
+        assert_eq!(response.unwrap(), b"12.5");
+    }
+
+    #[tokio::test]
+    async fn test_resync() {
+        init_logger();
+        let mut wrapper = device(std::vec![Some(b"\r\n>>")]);
+        wrapper.uart.rx.extend_from_slice(b"#!garbage");
+        wrapper.resync(PROMPT, TIMEOUT).await.unwrap();
+        assert_eq!(wrapper.uart.tx, b"\r\n");
+
+        // No prompt, no resync.
+        let mut wrapper = device(std::vec![Some(b"garbage")]);
+        let report = wrapper.resync(PROMPT, TIMEOUT).await.unwrap_err();
+        assert_eq!(report.error, FeroxError::UartRequestTimeout);
+    }
+
+    #[tokio::test]
+    async fn test_query_recovers_after_garbage() {
+        init_logger();
+        // The first attempt times out, the blank line of the resync is answered with garbage
+        // and the prompt, and the second attempt goes through.
+        let mut wrapper = device(std::vec![
+            None,
+            Some(b"#!garbage\r\n>>"),
+            Some(b"12.5\r\n>>"),
+        ])
+        .with_resync(PROMPT);
+        let mut buf = [0u8; 16];
+        let policy = Fixed::new(2, Duration::from_millis(1));
+        let response = wrapper.query(b"ilaser", &mut buf, TIMEOUT, &policy).await;
+        assert_eq!(response.unwrap(), b"12.5");
+        assert_eq!(wrapper.attempts(), 2);
+        assert_eq!(wrapper.uart.tx, b"ilaser\r\n\r\nilaser\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_write_line_endings() {
+        init_logger();
+        let mut wrapper = UartWrapper::new(port(std::vec![]), DefaultPostProcessor);
+        wrapper.write_all(b"ilaser 12.5\nsave\r").await.unwrap();
+        wrapper.write_all(b"\nversion\r\n").await.unwrap();
+        wrapper.flush().await.unwrap();
+        assert_eq!(wrapper.uart.tx, b"ilaser 12.5\r\nsave\r\nversion\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_protocol_line_ending() {
+        init_logger();
+        let protocol = Protocol::new(DefaultPostProcessor).with_line_ending(b";\r");
+        let mut wrapper = UartWrapper::with_protocol(port(std::vec![]), protocol);
+        wrapper.write_line("pos 1.5").await.unwrap();
+        wrapper.write_all(b"stop\r\ngo\n\r").await.unwrap();
+        wrapper.flush().await.unwrap();
+        assert_eq!(wrapper.uart.tx, b"pos 1.5;\rstop;\rgo;\r\r");
+    }
+
+    #[tokio::test]
+    async fn test_buf_read() {
+        use embedded_io_async::BufRead;
+
+        init_logger();
+        let mut wrapper = UartWrapper::new(port(std::vec![b"V0.17\r\n>>"]), DefaultPostProcessor);
+        assert_eq!(wrapper.fill_buf().await.unwrap(), b"V0.17\r\n>>");
+        wrapper.consume(7);
+        assert_eq!(wrapper.fill_buf().await.unwrap(), b">>");
+        let mut buf = [0u8; 4];
+        assert_eq!(wrapper.read(&mut buf).await.unwrap(), 2);
+        assert_eq!(wrapper.fill_buf().await.unwrap(), b"");
     }
 }
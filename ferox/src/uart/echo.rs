@@ -0,0 +1,92 @@
+//! Devices that echo commands.
+//!
+//! Devices such as the CTL200 and the SMC repeat every command back before they answer, so a
+//! response reads `ilaser\r\n12.5`. How that first line is treated is an [`EchoMode`].
+
+use crate::proto::{error::Error as FeroxError, Result as FeroxResult};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EchoMode {
+    /// The device does not echo; responses are kept whole.
+    #[default]
+    Off,
+    /// The first line is the echo and is dropped unseen.
+    Strip,
+    /// The first line is dropped, but must repeat the request, otherwise the response belongs
+    /// to some other request and is rejected with [`FeroxError::EchoMismatch`].
+    Verify,
+}
+
+/// Returns `response` without the echo of `request`, as `mode` says.
+///
+/// The echo ends at the first line break, `\r\n` or `\n`. A response without one is only the
+/// echo. What is returned is always a suffix of `response`.
+pub fn strip_echo<'a>(mode: EchoMode, request: &[u8], response: &'a [u8]) -> FeroxResult<&'a [u8]> {
+    if mode == EchoMode::Off {
+        return Ok(response);
+    }
+    let (echo, rest) = match response.iter().position(|&b| b == b'\n') {
+        Some(pos) => (&response[..pos], &response[pos + 1..]),
+        None => (response, &response[response.len()..]),
+    };
+    let echo = echo.strip_suffix(b"\r").unwrap_or(echo);
+    if mode == EchoMode::Verify && echo != request {
+        return Err(FeroxError::EchoMismatch);
+    }
+    Ok(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    #[test]
+    fn test_off() {
+        init_logger();
+        assert_eq!(
+            strip_echo(EchoMode::Off, b"ilaser", b"ilaser\r\n12.5"),
+            Ok(&b"ilaser\r\n12.5"[..])
+        );
+    }
+
+    #[test]
+    fn test_strip() {
+        init_logger();
+        assert_eq!(
+            strip_echo(EchoMode::Strip, b"ilaser", b"ilaser\r\n12.5"),
+            Ok(&b"12.5"[..])
+        );
+        assert_eq!(
+            strip_echo(EchoMode::Strip, b"bia", b"garbled\n1.2.3"),
+            Ok(&b"1.2.3"[..])
+        );
+        assert_eq!(
+            strip_echo(EchoMode::Strip, b"status", b"status\r\nlason 1\r\ntecon 0"),
+            Ok(&b"lason 1\r\ntecon 0"[..])
+        );
+        assert_eq!(strip_echo(EchoMode::Strip, b"save", b"save"), Ok(&b""[..]));
+    }
+
+    #[test]
+    fn test_verify() {
+        init_logger();
+        assert_eq!(
+            strip_echo(EchoMode::Verify, b"ilaser 12.5", b"ilaser 12.5\r\n12.5"),
+            Ok(&b"12.5"[..])
+        );
+        assert_eq!(
+            strip_echo(EchoMode::Verify, b"errclr", b"errclr"),
+            Ok(&b""[..])
+        );
+        assert_eq!(
+            strip_echo(EchoMode::Verify, b"ilaser", b"lason\r\n1"),
+            Err(FeroxError::EchoMismatch)
+        );
+        assert_eq!(
+            strip_echo(EchoMode::Verify, b"ilaser", b"ilaser 1\r\n1"),
+            Err(FeroxError::EchoMismatch)
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Reading terminated frames from a byte stream.
+
+use embedded_io_async::Read;
+
+use crate::proto::{
+    error::{Error as FeroxError, ErrorReport},
+    Result as FeroxResult,
+};
+
+/// How a frame ends.
+///
+/// Besides a fixed terminator, a pattern can require the last line of the frame to start with
+/// given bytes, e.g. `ERR` in `ERR: unknown command\r\n>>`, which tells an error banner apart
+/// from a normal response ending in the same prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EndPattern<'a> {
+    /// The bytes that end the frame, e.g. `\r\n>>`.
+    pub end: &'a [u8],
+    /// What the last line before `end` must start with, if anything.
+    pub line_start: Option<&'a [u8]>,
+}
+
+impl<'a> EndPattern<'a> {
+    /// Any frame that ends in `end`.
+    pub const fn new(end: &'a [u8]) -> Self {
+        Self {
+            end,
+            line_start: None,
+        }
+    }
+
+    /// A frame that ends in `end` and whose last line starts with `line_start`.
+    pub const fn line(line_start: &'a [u8], end: &'a [u8]) -> Self {
+        Self {
+            end,
+            line_start: Some(line_start),
+        }
+    }
+}
+
+/// Splits a stream into frames that end in a terminator, e.g. `\r\n>>`.
+///
+/// Bytes are kept in a ring buffer of `N` bytes between calls, so bytes that arrive after a
+/// terminator, in the same read or not, start the next frame instead of being lost.
+pub struct FramedReader<const N: usize> {
+    buf: [u8; N],
+    // Index of the first buffered byte.
+    start: usize,
+    // Number of buffered bytes.
+    len: usize,
+    // Bytes of the last returned frame, including its terminator, dropped on the next call.
+    consumed: usize,
+    // Whether the rest of a frame that overflowed is still to be skipped.
+    skipping: bool,
+}
+
+impl<const N: usize> Default for FramedReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FramedReader<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+            consumed: 0,
+            skipping: false,
+        }
+    }
+
+    /// Number of bytes read from the stream but not returned yet.
+    pub fn buffered(&self) -> usize {
+        self.len - self.consumed
+    }
+
+    /// Drops all buffered bytes.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.consumed = 0;
+        self.skipping = false;
+    }
+
+    /// Returns the next frame without its terminator, reading from `reader` as needed.
+    ///
+    /// A frame that does not fit into the buffer fails with [`FeroxError::BufferOverflow`], and
+    /// the rest of it, up to and including its terminator, is skipped by the next call.
+    pub async fn read_frame<R: Read>(
+        &mut self,
+        reader: &mut R,
+        terminator: &[u8],
+    ) -> FeroxResult<&[u8], ErrorReport> {
+        let (_, frame) = self
+            .read_frame_any(reader, &[EndPattern::new(terminator)])
+            .await?;
+        Ok(frame)
+    }
+
+    /// Like [`FramedReader::read_frame`], but the frame ends with whichever of `patterns`
+    /// matches first. Returns the index of that pattern and the frame without its `end`.
+    ///
+    /// If several patterns end at the same byte, the one listed first wins, so list more
+    /// specific patterns before more general ones.
+    pub async fn read_frame_any<R: Read>(
+        &mut self,
+        reader: &mut R,
+        patterns: &[EndPattern<'_>],
+    ) -> FeroxResult<(usize, &[u8]), ErrorReport> {
+        self.advance(self.consumed);
+        self.consumed = 0;
+
+        let longest = patterns.iter().map(|p| p.end.len()).max().unwrap_or(0);
+        let mut searched = 0;
+        loop {
+            if let Some((index, pos)) = self.find(patterns, searched) {
+                let end = pos + patterns[index].end.len();
+                if self.skipping {
+                    self.advance(end);
+                    self.skipping = false;
+                    searched = 0;
+                    continue;
+                }
+                self.consumed = end;
+                return Ok((index, &self.contiguous()[..pos]));
+            }
+            // An end may straddle the bytes read next.
+            searched = (self.len + 1).saturating_sub(longest);
+
+            if self.len == N {
+                // Only the bytes an end may start with are kept.
+                self.advance(N - longest.saturating_sub(1).min(N));
+                searched = 0;
+                if !self.skipping {
+                    self.skipping = true;
+                    return Err(FeroxError::BufferOverflow.into());
+                }
+            }
+            let tail = (self.start + self.len) % N;
+            let end = if tail < self.start { self.start } else { N };
+            let sz = reader
+                .read(&mut self.buf[tail..end])
+                .await
+                .map_err(|e| ErrorReport::from_io(FeroxError::ReadError, e))?;
+            if sz == 0 {
+                return Err(FeroxError::ReadError.into());
+            }
+            self.len += sz;
+        }
+    }
+
+    /// Moves buffered bytes that are not part of a frame into `out`, like [`Read::read`].
+    pub fn read_buffered(&mut self, out: &mut [u8]) -> usize {
+        self.advance(self.consumed);
+        self.consumed = 0;
+        let n = out.len().min(self.len);
+        for (i, b) in out[..n].iter_mut().enumerate() {
+            *b = self.at(i);
+        }
+        self.advance(n);
+        n
+    }
+
+    /// Returns the buffered bytes that are not part of a frame, reading from `reader` if there
+    /// are none, like [`embedded_io_async::BufRead::fill_buf`].
+    pub async fn fill_buf<R: Read>(&mut self, reader: &mut R) -> Result<&[u8], R::Error> {
+        self.advance(self.consumed);
+        self.consumed = 0;
+        if self.len == 0 {
+            self.len = reader.read(&mut self.buf).await?;
+        }
+        Ok(self.contiguous())
+    }
+
+    /// Returns a reader that hands out the buffered bytes that are not part of a frame first and
+    /// then reads from `reader`, e.g. to decode the rest of a response as it streams in.
+    pub fn chain<'a, R: Read>(&'a mut self, reader: &'a mut R) -> Chain<'a, R, N> {
+        Chain {
+            framed: self,
+            reader,
+        }
+    }
+
+    /// Drops the first `amt` bytes returned by [`FramedReader::fill_buf`].
+    pub fn consume(&mut self, amt: usize) {
+        self.advance(self.consumed + amt.min(self.len - self.consumed));
+        self.consumed = 0;
+    }
+
+    fn at(&self, i: usize) -> u8 {
+        self.buf[(self.start + i) % N]
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.len -= n;
+        self.start = if self.len == 0 {
+            0
+        } else {
+            (self.start + n) % N
+        };
+    }
+
+    // The first pattern whose end starts at or after `from`, and that position.
+    fn find(&self, patterns: &[EndPattern<'_>], from: usize) -> Option<(usize, usize)> {
+        (from..self.len).find_map(|i| {
+            patterns
+                .iter()
+                .position(|p| self.matches(p, i))
+                .map(|index| (index, i))
+        })
+    }
+
+    fn matches(&self, pattern: &EndPattern<'_>, pos: usize) -> bool {
+        let starts_with = |at: usize, bytes: &[u8]| {
+            at + bytes.len() <= self.len
+                && bytes.iter().enumerate().all(|(j, &b)| self.at(at + j) == b)
+        };
+        if pattern.end.is_empty() || !starts_with(pos, pattern.end) {
+            return false;
+        }
+        match pattern.line_start {
+            None => true,
+            Some(line_start) => {
+                let line = (0..pos)
+                    .rev()
+                    .find(|&i| self.at(i) == b'\n')
+                    .map_or(0, |i| i + 1);
+                line + line_start.len() <= pos && starts_with(line, line_start)
+            }
+        }
+    }
+
+    // The buffered bytes in order, rotating the ring if they wrap around its end.
+    fn contiguous(&mut self) -> &[u8] {
+        if self.start + self.len > N {
+            self.buf.rotate_left(self.start);
+            self.start = 0;
+        }
+        &self.buf[self.start..self.start + self.len]
+    }
+}
+
+/// A [`FramedReader`] followed by the stream it reads from, see [`FramedReader::chain`].
+pub struct Chain<'a, R, const N: usize> {
+    framed: &'a mut FramedReader<N>,
+    reader: &'a mut R,
+}
+
+impl<R: Read, const N: usize> embedded_io_async::ErrorType for Chain<'_, R, N> {
+    type Error = R::Error;
+}
+
+impl<R: Read, const N: usize> Read for Chain<'_, R, N> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.framed.read_buffered(buf) {
+            0 => self.reader.read(buf).await,
+            n => Ok(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::testing::helpers::{init_logger, ChunkedReader};
+
+    #[tokio::test]
+    async fn test_terminator_across_chunks() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"V0.", b"17\r", b"\n>", b">"]);
+        let mut framed = FramedReader::<16>::new();
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\r\n>>").await.unwrap(),
+            b"V0.17"
+        );
+        assert_eq!(framed.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_bytes_after_terminator() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"one\r\ntwo\r\nthr", b"ee\r\n"]);
+        let mut framed = FramedReader::<32>::new();
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\r\n").await.unwrap(),
+            b"one"
+        );
+        assert_eq!(framed.buffered(), 8);
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\r\n").await.unwrap(),
+            b"two"
+        );
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\r\n").await.unwrap(),
+            b"three"
+        );
+        assert_eq!(
+            framed
+                .read_frame(&mut reader, b"\r\n")
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::ReadError
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wraps_around() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"abcde\n12", b"345\nxy", b"z\n"]);
+        let mut framed = FramedReader::<10>::new();
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\n").await.unwrap(),
+            b"abcde"
+        );
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\n").await.unwrap(),
+            b"12345"
+        );
+        assert_eq!(framed.read_frame(&mut reader, b"\n").await.unwrap(), b"xyz");
+    }
+
+    #[tokio::test]
+    async fn test_overflow() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"0123", b"4567", b"89ok\n", b"ok\n"]);
+        let mut framed = FramedReader::<8>::new();
+        assert_eq!(
+            framed
+                .read_frame(&mut reader, b"\n")
+                .await
+                .unwrap_err()
+                .error,
+            FeroxError::BufferOverflow
+        );
+        // The rest of the long frame is skipped, not taken for the next one.
+        assert_eq!(framed.read_frame(&mut reader, b"\n").await.unwrap(), b"ok");
+
+        // The end of the long frame straddles the end of the buffer.
+        let mut reader = ChunkedReader(std::vec![b"0123", b"456\r", b"\nok\r\n"]);
+        let mut framed = FramedReader::<8>::new();
+        assert!(framed.read_frame(&mut reader, b"\r\n").await.is_err());
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\r\n").await.unwrap(),
+            b"ok"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_any_pattern() {
+        init_logger();
+        let patterns = [
+            EndPattern::line(b"ERR", b"\r\n>>"),
+            EndPattern::new(b"\r\n>>"),
+            EndPattern::new(b"\n\r\n"),
+        ];
+        let mut reader = ChunkedReader(std::vec![
+            b"ilaser\r\n12.5\r",
+            b"\n>>ilaser 99\r\nERR: out of range\r\n>>",
+            b"bia\r\n1.2.3\n\r\n",
+        ]);
+        let mut framed = FramedReader::<64>::new();
+        assert_eq!(
+            framed.read_frame_any(&mut reader, &patterns).await.unwrap(),
+            (1, &b"ilaser\r\n12.5"[..])
+        );
+        assert_eq!(
+            framed.read_frame_any(&mut reader, &patterns).await.unwrap(),
+            (0, &b"ilaser 99\r\nERR: out of range"[..])
+        );
+        assert_eq!(
+            framed.read_frame_any(&mut reader, &patterns).await.unwrap(),
+            (2, &b"bia\r\n1.2.3"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fill_buf() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"frame\nre", b"st"]);
+        let mut framed = FramedReader::<16>::new();
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\n").await.unwrap(),
+            b"frame"
+        );
+        assert_eq!(framed.fill_buf(&mut reader).await.unwrap(), b"re");
+        framed.consume(1);
+        assert_eq!(framed.fill_buf(&mut reader).await.unwrap(), b"e");
+        framed.consume(1);
+        assert_eq!(framed.fill_buf(&mut reader).await.unwrap(), b"st");
+        framed.consume(2);
+        assert_eq!(framed.fill_buf(&mut reader).await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn test_read_buffered() {
+        init_logger();
+        let mut reader = ChunkedReader(std::vec![b"frame\nrest"]);
+        let mut framed = FramedReader::<16>::new();
+        assert_eq!(
+            framed.read_frame(&mut reader, b"\n").await.unwrap(),
+            b"frame"
+        );
+        let mut out = [0u8; 8];
+        assert_eq!(framed.read_buffered(&mut out), 4);
+        assert_eq!(&out[..4], b"rest");
+        assert_eq!(framed.buffered(), 0);
+    }
+}
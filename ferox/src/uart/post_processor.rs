@@ -1,17 +1,42 @@
+use super::echo::{strip_echo, EchoMode};
+use crate::proto::{error::Error as FeroxError, Result as FeroxResult};
+
+/// Turns the raw response to `request` into what the caller gets, or rejects it.
+///
+/// Processors are chained with [`PostProcessor::then`], e.g.
+/// `StripPrompt(b">>").then(Trim).then(ErrorPrefix(b"ERR"))`.
 pub trait PostProcessor {
-    fn post_process<'a>(&self, data: &'a [u8]) -> &'a [u8];
+    fn post_process<'a>(&self, request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]>;
+
+    /// Runs `next` on the output of this processor.
+    fn then<Q: PostProcessor>(self, next: Q) -> Chain<Self, Q>
+    where
+        Self: Sized,
+    {
+        Chain(self, next)
+    }
+}
+
+/// Two processors run one after the other, see [`PostProcessor::then`].
+pub struct Chain<A, B>(pub A, pub B);
+
+impl<A: PostProcessor, B: PostProcessor> PostProcessor for Chain<A, B> {
+    fn post_process<'a>(&self, request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        let data = self.0.post_process(request, data)?;
+        self.1.post_process(request, data)
+    }
 }
 
 // VaPostProcessor should remove the first line of the data.
 pub struct VaPostProcessor;
 
 impl PostProcessor for VaPostProcessor {
-    fn post_process<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+    fn post_process<'a>(&self, _request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
         // Find first newline
         if let Some(pos) = data.iter().position(|&x| x == b'\n') {
-            &data[pos + 1..]
+            Ok(&data[pos + 1..])
         } else {
-            data
+            Ok(data)
         }
     }
 }
@@ -20,7 +45,103 @@ impl PostProcessor for VaPostProcessor {
 pub struct DefaultPostProcessor;
 
 impl PostProcessor for DefaultPostProcessor {
-    fn post_process<'a>(&self, data: &'a [u8]) -> &'a [u8] {
-        data
+    fn post_process<'a>(&self, _request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        Ok(data)
+    }
+}
+
+/// Removes a trailing prompt such as `>>`, if there is one.
+pub struct StripPrompt(pub &'static [u8]);
+
+impl PostProcessor for StripPrompt {
+    fn post_process<'a>(&self, _request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        Ok(data.strip_suffix(self.0).unwrap_or(data))
+    }
+}
+
+/// Removes leading and trailing ASCII whitespace, including line breaks.
+pub struct Trim;
+
+impl PostProcessor for Trim {
+    fn post_process<'a>(&self, _request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        Ok(data.trim_ascii())
+    }
+}
+
+/// Removes the first line if it echoes the request, and keeps it otherwise.
+pub struct StripMatchingEcho;
+
+impl PostProcessor for StripMatchingEcho {
+    fn post_process<'a>(&self, request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        Ok(strip_echo(EchoMode::Verify, request, data).unwrap_or(data))
+    }
+}
+
+/// Rejects responses starting with an error marker such as `ERR` with
+/// [`FeroxError::DeviceError`].
+pub struct ErrorPrefix(pub &'static [u8]);
+
+impl PostProcessor for ErrorPrefix {
+    fn post_process<'a>(&self, _request: &[u8], data: &'a [u8]) -> FeroxResult<&'a [u8]> {
+        if data.starts_with(self.0) {
+            return Err(FeroxError::DeviceError);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    #[test]
+    fn test_built_ins() {
+        init_logger();
+        assert_eq!(
+            StripPrompt(b"\r\n>>").post_process(b"", b"12.5\r\n>>"),
+            Ok(&b"12.5"[..])
+        );
+        assert_eq!(
+            StripPrompt(b">>").post_process(b"", b"12.5"),
+            Ok(&b"12.5"[..])
+        );
+        assert_eq!(
+            Trim.post_process(b"", b" \r\n1.2.3\n\r\n"),
+            Ok(&b"1.2.3"[..])
+        );
+        assert_eq!(
+            StripMatchingEcho.post_process(b"ilaser", b"ilaser\r\n12.5"),
+            Ok(&b"12.5"[..])
+        );
+        assert_eq!(
+            StripMatchingEcho.post_process(b"ilaser", b"lason\r\n1"),
+            Ok(&b"lason\r\n1"[..])
+        );
+        assert_eq!(
+            ErrorPrefix(b"ERR").post_process(b"", b"ERR: unknown command"),
+            Err(FeroxError::DeviceError)
+        );
+        assert_eq!(
+            ErrorPrefix(b"ERR").post_process(b"", b"V0.17"),
+            Ok(&b"V0.17"[..])
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        init_logger();
+        let pipeline = StripMatchingEcho
+            .then(StripPrompt(b">>"))
+            .then(Trim)
+            .then(ErrorPrefix(b"ERR"));
+        assert_eq!(
+            pipeline.post_process(b"version", b"version\r\nV0.17\r\n>>"),
+            Ok(&b"V0.17"[..])
+        );
+        assert_eq!(
+            pipeline.post_process(b"ilaser 900", b"ilaser 900\r\n ERR: out of range\r\n>>"),
+            Err(FeroxError::DeviceError)
+        );
     }
 }
@@ -0,0 +1,66 @@
+//! How a port talks to the device on the other end.
+//!
+//! A [`Protocol`] bundles what [`UartWrapper`](super::UartWrapper) needs to know about a
+//! device: the line ending sent after each command, the terminator that ends each response,
+//! whether the device echoes commands and how responses are post-processed. Profiles for the
+//! instruments we drive are below, e.g. `UartWrapper::with_protocol(uart, ctl200())`.
+
+use super::{
+    echo::EchoMode,
+    post_processor::{DefaultPostProcessor, PostProcessor},
+};
+
+/// Per-port protocol settings, see the [module docs](self).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Protocol<P> {
+    /// Sent after every command, e.g. `\r\n`, a bare `\r`, or `;\r\n` for devices that want
+    /// an extra terminator.
+    pub line_ending: &'static [u8],
+    /// Ends every response, e.g. the `\r\n>>` prompt.
+    pub terminator: &'static [u8],
+    pub echo: EchoMode,
+    pub post_processor: P,
+}
+
+impl<P: PostProcessor> Protocol<P> {
+    /// Lines end in `\r\n` both ways and nothing is echoed.
+    pub fn new(post_processor: P) -> Self {
+        Self {
+            line_ending: b"\r\n",
+            terminator: b"\r\n",
+            echo: EchoMode::Off,
+            post_processor,
+        }
+    }
+
+    pub fn with_line_ending(mut self, line_ending: &'static [u8]) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn with_terminator(mut self, terminator: &'static [u8]) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    pub fn with_echo(mut self, echo: EchoMode) -> Self {
+        self.echo = echo;
+        self
+    }
+}
+
+/// Koheron CTL200: commands end in `\r\n`, are echoed, and every response ends with the
+/// `\r\n>>` prompt.
+pub fn ctl200() -> Protocol<DefaultPostProcessor> {
+    Protocol::new(DefaultPostProcessor)
+        .with_terminator(b"\r\n>>")
+        .with_echo(EchoMode::Verify)
+}
+
+/// SMC motion controller: commands end in `\r\n`, are echoed, and responses end with a blank
+/// line, `\n\r\n`.
+pub fn va_smc() -> Protocol<DefaultPostProcessor> {
+    Protocol::new(DefaultPostProcessor)
+        .with_terminator(b"\n\r\n")
+        .with_echo(EchoMode::Strip)
+}
@@ -0,0 +1,204 @@
+//! When failed queries are sent again.
+//!
+//! A [`RetryPolicy`] sees every failed attempt and either gives up or names a delay before the
+//! next one. The built-in policies can be nested, e.g.
+//! `ExceptCommands::new(&["save"], OnlyOnTimeout(Fixed::new(3, Duration::from_millis(50))))`.
+
+use embassy_time::Duration;
+
+use crate::proto::error::{Error as FeroxError, ErrorReport};
+
+/// Decides whether, and after how long, a failed query is sent again.
+pub trait RetryPolicy {
+    /// Called after attempt number `attempt` (counting from 1) of `request` failed with
+    /// `error`. Returns how long to wait before the next attempt, or `None` to give up.
+    fn retry(&self, request: &[u8], attempt: u32, error: &ErrorReport) -> Option<Duration>;
+}
+
+/// Up to `attempts` attempts in total, `delay` apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed {
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+impl Fixed {
+    pub const fn new(attempts: u32, delay: Duration) -> Self {
+        Self { attempts, delay }
+    }
+}
+
+impl RetryPolicy for Fixed {
+    fn retry(&self, _request: &[u8], attempt: u32, _error: &ErrorReport) -> Option<Duration> {
+        (attempt < self.attempts).then_some(self.delay)
+    }
+}
+
+/// Up to `attempts` attempts in total, waiting twice as long after each failure, from `base`
+/// up to `max`.
+///
+/// Each delay is cut by a pseudo-random amount of up to half, derived from `seed` and the
+/// attempt, so that devices sharing a bus with different seeds do not retry in lockstep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    pub attempts: u32,
+    pub base: Duration,
+    pub max: Duration,
+    pub seed: u32,
+}
+
+impl ExponentialBackoff {
+    pub const fn new(attempts: u32, base: Duration, max: Duration) -> Self {
+        Self {
+            attempts,
+            base,
+            max,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    pub const fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn retry(&self, _request: &[u8], attempt: u32, _error: &ErrorReport) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        let factor = 1u64
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let delay = self.base.as_ticks().saturating_mul(factor);
+        let delay = delay.min(self.max.as_ticks());
+        // xorshift32 over seed and attempt; zero would stay zero, hence the `| 1`.
+        let mut x = (self.seed ^ attempt.wrapping_mul(0x85EB_CA6B)) | 1;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        let jitter = u128::from(delay / 2) * u128::from(x) / u128::from(u32::MAX);
+        Some(Duration::from_ticks(delay - jitter as u64))
+    }
+}
+
+/// Retries as `inner` does, but only after timeouts. Any other error means the device
+/// answered, so sending the request again would not help.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnlyOnTimeout<P>(pub P);
+
+impl<P: RetryPolicy> RetryPolicy for OnlyOnTimeout<P> {
+    fn retry(&self, request: &[u8], attempt: u32, error: &ErrorReport) -> Option<Duration> {
+        match error.error {
+            FeroxError::UartRequestTimeout => self.0.retry(request, attempt, error),
+            _ => None,
+        }
+    }
+}
+
+/// Retries as `inner` does, except for `commands`, e.g. `save`, which must not run twice.
+///
+/// The command is the first word of the request, compared ignoring ASCII case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExceptCommands<P> {
+    pub commands: &'static [&'static str],
+    pub inner: P,
+}
+
+impl<P> ExceptCommands<P> {
+    pub const fn new(commands: &'static [&'static str], inner: P) -> Self {
+        Self { commands, inner }
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for ExceptCommands<P> {
+    fn retry(&self, request: &[u8], attempt: u32, error: &ErrorReport) -> Option<Duration> {
+        let name = request
+            .split(|&b| b == b' ' || b == b'?')
+            .next()
+            .unwrap_or_default();
+        if self
+            .commands
+            .iter()
+            .any(|c| c.as_bytes().eq_ignore_ascii_case(name))
+        {
+            return None;
+        }
+        self.inner.retry(request, attempt, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::helpers::init_logger;
+
+    fn timeout() -> ErrorReport {
+        ErrorReport::new(FeroxError::UartRequestTimeout)
+    }
+
+    #[test]
+    fn test_fixed() {
+        init_logger();
+        let policy = Fixed::new(3, Duration::from_millis(50));
+        assert_eq!(
+            policy.retry(b"version", 1, &timeout()),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            policy.retry(b"version", 2, &timeout()),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(policy.retry(b"version", 3, &timeout()), None);
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        init_logger();
+        let base = Duration::from_millis(10);
+        let max = Duration::from_millis(50);
+        let policy = ExponentialBackoff::new(6, base, max).with_seed(42);
+        let mut delays = [Duration::MIN; 5];
+        for (attempt, delay) in (1..).zip(delays.iter_mut()) {
+            *delay = policy.retry(b"version", attempt, &timeout()).unwrap();
+        }
+        for (i, delay) in delays.iter().enumerate() {
+            let full = (base * (1 << i)).min(max);
+            assert!(*delay <= full && *delay >= full / 2, "{:?}", delays);
+        }
+        assert_eq!(policy.retry(b"version", 6, &timeout()), None);
+        // Attempt 0 waits as long as the first one.
+        let delay = policy.retry(b"version", 0, &timeout()).unwrap();
+        assert!(delay <= base && delay >= base / 2, "{:?}", delay);
+        // The same seed and attempt give the same delay.
+        assert_eq!(policy.retry(b"version", 2, &timeout()), Some(delays[1]));
+        assert_ne!(
+            policy.with_seed(7).retry(b"version", 2, &timeout()),
+            Some(delays[1])
+        );
+    }
+
+    #[test]
+    fn test_only_on_timeout() {
+        init_logger();
+        let policy = OnlyOnTimeout(Fixed::new(3, Duration::MIN));
+        assert_eq!(policy.retry(b"version", 1, &timeout()), Some(Duration::MIN));
+        let echo = ErrorReport::new(FeroxError::EchoMismatch);
+        assert_eq!(policy.retry(b"version", 1, &echo), None);
+    }
+
+    #[test]
+    fn test_except_commands() {
+        init_logger();
+        let policy = ExceptCommands::new(&["save", "errclr"], Fixed::new(3, Duration::MIN));
+        assert_eq!(policy.retry(b"save", 1, &timeout()), None);
+        assert_eq!(policy.retry(b"ERRCLR", 1, &timeout()), None);
+        assert_eq!(policy.retry(b"save?", 1, &timeout()), None);
+        assert_eq!(policy.retry(b"saved 1", 1, &timeout()), Some(Duration::MIN));
+        assert_eq!(
+            policy.retry(b"ilaser 12.5", 1, &timeout()),
+            Some(Duration::MIN)
+        );
+    }
+}
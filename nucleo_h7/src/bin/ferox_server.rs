@@ -13,16 +13,21 @@ use embassy_stm32::{
 use embassy_time::{Duration, Timer};
 use embedded_io_async::{Read, Write};
 use ferox::{
-    drivers::koheron::ctl200::Ctl200Request,
+    drivers::koheron::{ctl200, ctl200::Ctl200Request},
     proto::{
-        ascii::{from_bytes, to_bytes},
-        error::Error,
-        ferox::{FeroxRequest, SmcRequest},
+        ascii::{deser::ErrorContext, from_bytes, from_bytes_relaxed, to_slice},
+        binary::{from_frame, to_frame, MAX_FRAME_SIZE},
+        command::Commands,
+        error::{Error, ErrorReport},
+        ferox::{FeroxRequest, FeroxResponse, SmcRequest},
         Result,
     },
     uart::{
-        post_processor::{PostProcessor, VaPostProcessor},
-        read_until, UartWrapper,
+        framed::EndPattern,
+        post_processor::{DefaultPostProcessor, PostProcessor},
+        protocol,
+        retry::{ExceptCommands, ExponentialBackoff, OnlyOnTimeout},
+        Framing, UartWrapper,
     },
     MAX_STRING_SIZE,
 };
@@ -43,10 +48,28 @@ bind_interrupts!(struct Irqs7 {
 
 pub const CMD_PROMPT: &[u8] = b"\r\n";
 pub const CTL200_END: &[u8] = b"\r\n>>";
-pub const SMC_END: &[u8] = b"\n\r\n";
+/// Ends of a CTL200 response: an error banner, or anything else before the prompt.
+const CTL200_ENDS: [EndPattern; 2] = [
+    EndPattern::line(b"ERR", CTL200_END),
+    EndPattern::new(CTL200_END),
+];
+const CTL200_ERROR: usize = 0;
 
-const MAX_RETRIES: i32 = 3;
+// Every query is tried up to 3 times. Only timeouts are retried, as anything else means the
+// device did answer.
+const RETRY: OnlyOnTimeout<ExponentialBackoff> = OnlyOnTimeout(ExponentialBackoff::new(
+    3,
+    Duration::from_millis(20),
+    Duration::from_millis(200),
+));
+const CTL200_RETRY: ExceptCommands<OnlyOnTimeout<ExponentialBackoff>> =
+    ExceptCommands::new(ctl200::NON_IDEMPOTENT, RETRY);
+// Longest command sent to a device.
+const REQUEST_SIZE: usize = 32;
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(3_000);
+// Longest error line sent to the controller, e.g. `0x2005 at byte 7 near "4x2", expected number`
+// or `0x2006 at byte 4 near "1": missing field `b``.
+const ERROR_LINE_SIZE: usize = 128;
 
 pub struct FeroxServer<U0, U1, U2, P0, P1, P2> {
     /// UART4 is used to receive/send commands from the host or external devices
@@ -55,6 +78,11 @@ pub struct FeroxServer<U0, U1, U2, P0, P1, P2> {
     ctl200: UartWrapper<U1, P1>,
     /// UART7 is used to communicate with the smc device
     smc: UartWrapper<U2, P2>,
+    /// Where the last request from the controller failed to parse.
+    request_error: Option<ErrorContext>,
+    /// How the controller framed its last request. Responses are framed the same way, so a
+    /// session switches to binary with its first binary frame and back with its first line.
+    framing: Framing,
 }
 
 type UW<U, P> = UartWrapper<U, P>;
@@ -73,32 +101,47 @@ where
             controller,
             ctl200,
             smc,
+            request_error: None,
+            framing: Framing::Ascii,
         }
     }
 
-    async fn handle_all_versions(&mut self) -> Result<()> {
+    async fn handle_all_versions(&mut self) -> Result<(), ErrorReport> {
         info!("Handling AllVersions request");
-        let ctl200_req_str =
-            to_bytes(&Ctl200Request::Version).map_err(|_| Error::Ctl200RequestSerializeError)?;
-        let smc_req_str =
-            to_bytes(&SmcRequest::Version(None)).map_err(|_| Error::SmcRequestSerializeError)?;
+        let mut ctl200_req_buf = [0u8; REQUEST_SIZE];
+        let len = to_slice(&Ctl200Request::Version, &mut ctl200_req_buf)
+            .map_err(|_| Error::Ctl200RequestSerializeError)?;
+        let ctl200_req_str = &ctl200_req_buf[..len];
+        let mut smc_req_buf = [0u8; REQUEST_SIZE];
+        let len = to_slice(&SmcRequest::Version(None), &mut smc_req_buf)
+            .map_err(|_| Error::SmcRequestSerializeError)?;
+        let smc_req_str = &smc_req_buf[..len];
 
         // 1. Send to ctl200
         let mut response_buf = [0u8; MAX_STRING_SIZE];
         debug!(
             "Querying CTL200 version with request: {:?}",
-            core::str::from_utf8(&ctl200_req_str).unwrap_or("<invalid>")
+            core::str::from_utf8(ctl200_req_str).unwrap_or("<invalid>")
         );
-        let ctl_processed_resp = self
+        let (end, ctl_processed_resp) = self
             .ctl200
-            .query_with_pattern(
-                &ctl200_req_str,
-                CTL200_END,
+            .query_with_patterns(
+                ctl200_req_str,
+                &CTL200_ENDS,
                 &mut response_buf,
                 DEFAULT_TIMEOUT,
-                MAX_RETRIES,
+                &CTL200_RETRY,
             )
             .await?;
+        if end == CTL200_ERROR {
+            error!(
+                "CTL200 reported an error: {:?}",
+                core::str::from_utf8(ctl_processed_resp).unwrap_or("<invalid>")
+            );
+            return Err(ErrorReport::new(Error::DeviceError)
+                .with_device("ctl200")
+                .with_command(ctl200_req_str));
+        }
         let ctl200_ver =
             from_bytes::<&[u8]>(ctl_processed_resp).map_err(|_| Error::InvalidResponse)?;
         debug!(
@@ -112,13 +155,7 @@ where
         debug!("Querying SMC version");
         let smc_processed_resp = self
             .smc
-            .query_with_pattern(
-                &smc_req_str,
-                SMC_END,
-                &mut smc_response_buf,
-                DEFAULT_TIMEOUT,
-                MAX_RETRIES,
-            )
+            .query(smc_req_str, &mut smc_response_buf, DEFAULT_TIMEOUT, &RETRY)
             .await?;
         let smc_ver =
             from_bytes::<&[u8]>(smc_processed_resp).map_err(|_| Error::InvalidResponse)?;
@@ -127,6 +164,17 @@ where
             core::str::from_utf8(smc_ver).unwrap_or("<invalid>")
         );
 
+        if self.framing == Framing::Binary {
+            use core::str::from_utf8;
+            let response = FeroxResponse::AllVersions {
+                ctl200: from_utf8(ctl200_ver).map_err(|_| Error::InvalidResponse)?,
+                smc: from_utf8(smc_ver).map_err(|_| Error::InvalidResponse)?,
+            };
+            write_frame(&mut self.controller, &response).await?;
+            info!("AllVersions request completed successfully");
+            return Ok(());
+        }
+
         // 3. Assemble the final string
         let mut resp_buf: String<MAX_STRING_SIZE> = String::new();
         {
@@ -154,44 +202,104 @@ where
         Ok(())
     }
 
-    async fn process_ferox_request(&mut self, req: FeroxRequest) -> Result<()> {
+    async fn handle_help(&mut self) -> Result<(), ErrorReport> {
+        use core::fmt::Write;
+        for command in FeroxRequest::COMMANDS {
+            let mut line: String<MAX_STRING_SIZE> = String::new();
+            write!(line, "{}", command).map_err(|_| Error::FormatErrorInWriteResponse)?;
+            match self.framing {
+                Framing::Ascii => self.controller.write_line(&line).await?,
+                Framing::Binary => {
+                    write_frame(&mut self.controller, &FeroxResponse::Text(&line)).await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn process_ferox_request(&mut self, req: FeroxRequest) -> Result<(), ErrorReport> {
         match req {
             FeroxRequest::AllVersions => {
                 self.handle_all_versions().await?;
                 Ok(())
             }
+            FeroxRequest::Help => self.handle_help().await,
         }
     }
 
-    async fn read_ferox_request<'b>(&'b mut self) -> Result<FeroxRequest> {
+    async fn read_ferox_request<'b>(&'b mut self) -> Result<FeroxRequest, ErrorReport> {
         let mut cmd_buf = [0u8; MAX_STRING_SIZE];
-        let size = read_until(&mut self.controller, &mut cmd_buf, CMD_PROMPT).await?;
+        let (framing, size) = self
+            .controller
+            .read_request(&mut cmd_buf, CMD_PROMPT)
+            .await?;
+        self.framing = framing;
+        if framing == Framing::Binary {
+            debug!("Received binary frame of {} bytes", size);
+            return Ok(from_frame::<FeroxRequest>(&mut cmd_buf[..size])?);
+        }
         debug!(
             "Received command: {:?}",
             core::str::from_utf8(&cmd_buf[..size]).unwrap_or("<invalid utf8>")
         );
-        match ferox::proto::ascii::from_bytes::<FeroxRequest>(&cmd_buf[..size]) {
+        FeroxRequest::validate(&cmd_buf[..size])?;
+        match from_bytes_relaxed::<FeroxRequest>(&cmd_buf[..size]) {
             Ok(req) => Ok(req),
-            Err(_) => Err(Error::InvalidRequest),
+            Err(e) => {
+                error!("Invalid request: {}", e);
+                self.request_error = Some(e.context);
+                Err(Error::InvalidRequest.into())
+            }
         }
     }
 
-    async fn read_and_process(&mut self) -> Result<()> {
+    async fn read_and_process(&mut self) -> Result<(), ErrorReport> {
         // TODO(xguo): Keep the input / output buffer here, and reuse them for request / response handling.
         let req = self.read_ferox_request().await?;
         self.process_ferox_request(req).await
     }
 }
 
-async fn handle_error<UART, P>(err: Error, w: &mut UartWrapper<UART, P>) -> Result<()>
+async fn write_frame<UART, P>(
+    w: &mut UartWrapper<UART, P>,
+    response: &FeroxResponse<'_>,
+) -> Result<(), ErrorReport>
+where
+    UART: Read + Write,
+    P: PostProcessor,
+{
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    let len = to_frame(response, &mut frame)?;
+    w.write_bytes(&frame[..len]).await?;
+    Ok(())
+}
+
+async fn handle_error<UART, P>(
+    report: ErrorReport,
+    context: Option<ErrorContext>,
+    framing: Framing,
+    w: &mut UartWrapper<UART, P>,
+) -> Result<(), ErrorReport>
 where
     UART: Read + Write,
     P: PostProcessor,
 {
     use core::fmt::Write as FmtWrite;
-    let error_num = err as u16;
-    let mut s = String::<10>::new();
+    error!("Request failed: {}", report);
+    // Only the code goes over the wire, the rest of the report stays in the log.
+    let error_num = report.code();
+    if framing == Framing::Binary {
+        return write_frame(w, &FeroxResponse::Error(error_num)).await;
+    }
+    let mut s = String::<ERROR_LINE_SIZE>::new();
     write!(s, "0x{:04X}", error_num).map_err(|_| Error::FormatErrorInWriteError)?;
+    if let Some(context) = context {
+        // The code alone is still sent if the context does not fit.
+        let len = s.len();
+        if write!(s, " {}", context).is_err() {
+            s.truncate(len);
+        }
+    }
     w.write_line(s.as_str()).await?;
     Ok(())
 }
@@ -203,17 +311,22 @@ pub async fn run_server(
     smc: BufferedUart<'static, UART7>,
 ) -> ! {
     let mut server = FeroxServer::new(
-        UartWrapper::new(controller, VaPostProcessor),
-        UartWrapper::new(ctl200, VaPostProcessor),
-        UartWrapper::new(smc, VaPostProcessor),
+        UartWrapper::new(controller, DefaultPostProcessor).with_device("controller"),
+        UartWrapper::with_protocol(ctl200, protocol::ctl200())
+            .with_device("ctl200")
+            .with_resync(CTL200_END),
+        UartWrapper::with_protocol(smc, protocol::va_smc()).with_device("smc"),
     );
     loop {
         match server.read_and_process().await {
             Ok(_) => {
                 info!("Request processed successfully");
             }
-            Err(err) => {
-                if let Err(err) = handle_error(err, &mut server.controller).await {
+            Err(report) => {
+                let context = server.request_error.take();
+                if let Err(err) =
+                    handle_error(report, context, server.framing, &mut server.controller).await
+                {
                     error!("Failed to handle error: {}", err);
                 }
             }